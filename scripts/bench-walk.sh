@@ -0,0 +1,46 @@
+#!/usr/bin/env bash
+# Benchmark walking a synthetic tree of 16,000 files with 30 `json.schemas` associations.
+# Usage: scripts/bench-walk.sh [REV], where REV is an earlier revision to compare against.
+set -euo pipefail
+
+ROOT=$(git rev-parse --show-toplevel)
+WORK=$(mktemp -d)
+trap 'git -C "$ROOT" worktree remove --force "$WORK/baseline" 2>/dev/null || true; rm -rf "$WORK"' EXIT
+
+# Generate the tree: 32 directories of 500 files each, 30 of which are associated with a schema
+TREE="$WORK/tree"
+mkdir -p "$TREE/.vscode"
+echo '{ "type": "object" }' > "$TREE/schema.json"
+ASSOCIATIONS=""
+for DIR in $(seq 0 31); do
+    mkdir -p "$TREE/data/$DIR"
+    for FILE in $(seq 0 499); do
+        echo "{ \"id\": $FILE }" > "$TREE/data/$DIR/$FILE.json"
+    done
+    if [ "$DIR" -lt 30 ]; then
+        ASSOCIATIONS="$ASSOCIATIONS{ \"fileMatch\": [\"/data/$DIR/*.json\"], \"url\": \"./schema.json\" },"
+    fi
+done
+echo "{ \"json.schemas\": [${ASSOCIATIONS%,}] }" > "$TREE/.vscode/settings.json"
+
+# Build the current tree, and the baseline revision if given
+cargo build --release --manifest-path "$ROOT/Cargo.toml"
+BINARIES=("$ROOT/target/release/scheval")
+if [ $# -gt 0 ]; then
+    git -C "$ROOT" worktree add --detach "$WORK/baseline" "$1"
+    cargo build --release --manifest-path "$WORK/baseline/Cargo.toml" --target-dir "$ROOT/target/bench-baseline"
+    BINARIES=("$ROOT/target/bench-baseline/release/scheval" "${BINARIES[@]}")
+fi
+
+# Time each binary, without cached results
+cd "$TREE"
+for BINARY in "${BINARIES[@]}"; do
+    echo "$BINARY"
+    if command -v hyperfine > /dev/null; then
+        hyperfine --warmup 1 --prepare "rm -rf .scheval" "$BINARY -ivscode -isuffix > /dev/null"
+    else
+        "$BINARY" -ivscode -isuffix > /dev/null
+        rm -rf .scheval
+        time "$BINARY" -ivscode -isuffix > /dev/null
+    fi
+done
@@ -0,0 +1,174 @@
+//! Validation cache: Remember results of previous runs, so that unchanged instances are not validated again.
+
+use crate::{lock::Lockfile, retrieve::SchemaRetriever, vendor::sha256_hex};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use url::Url;
+
+/// Cache directory, relative to the base directory.
+pub(crate) const CACHE_DIR: &str = ".scheval/cache";
+/// Name of the results file in the cache directory.
+const RESULTS: &str = "results.json";
+
+/// Validation results of previous runs, keyed by [`result_key`].
+pub(crate) struct Cache {
+    /// The cache directory.
+    dir: PathBuf,
+    /// Map from key to validation errors of the instance, empty if it's valid.
+    results: HashMap<String, Vec<String>>,
+    /// Keys of previous results used in this run.
+    hits: Mutex<HashSet<String>>,
+    /// Results recorded in this run.
+    recorded: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl Cache {
+    /// Load the cache in `dir`. Missing, corrupted or outdated caches are treated as empty.
+    pub(crate) fn load(dir: &Path) -> Self {
+        let results = fs::read(dir.join(RESULTS))
+            .ok()
+            .and_then(|content| serde_json::from_slice::<Value>(&content).ok())
+            .filter(|cache| {
+                cache.get("version").and_then(Value::as_str) == Some(env!("CARGO_PKG_VERSION"))
+            })
+            .and_then(|mut cache| serde_json::from_value(cache["results"].take()).ok())
+            .unwrap_or_default();
+        Self {
+            dir: dir.to_path_buf(),
+            results,
+            hits: Mutex::new(HashSet::new()),
+            recorded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the errors of a previously validated instance, or `None` if it isn't cached.
+    pub(crate) fn get(&self, key: &str) -> Option<&[String]> {
+        let errors = self.results.get(key)?;
+        self.hits
+            .lock()
+            .expect("Cache lock poisoned")
+            .insert(key.to_string());
+        Some(errors)
+    }
+
+    /// Record the errors of a validated instance.
+    pub(crate) fn insert(&self, key: String, errors: Vec<String>) {
+        self.recorded
+            .lock()
+            .expect("Cache lock poisoned")
+            .insert(key, errors);
+    }
+
+    /// Write previous and newly recorded results back, if anything changed. If `evict`, previous results not used in this run are dropped, so that the cache doesn't grow without bound.
+    pub(crate) fn save(self, evict: bool) -> Result<(), Box<dyn Error>> {
+        let recorded = self.recorded.into_inner().expect("Cache lock poisoned");
+        let hits = self.hits.into_inner().expect("Cache lock poisoned");
+        let mut results = self.results;
+        let previous = results.len();
+        if evict {
+            results.retain(|key, _| hits.contains(key));
+        }
+        if recorded.is_empty() && results.len() == previous {
+            return Ok(());
+        }
+        results.extend(recorded);
+        let cache = json!({ "version": env!("CARGO_PKG_VERSION"), "results": results });
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first, so that an interrupted run doesn't leave a truncated cache
+        let temporary = self.dir.join(format!("{RESULTS}.tmp"));
+        fs::write(&temporary, serde_json::to_string(&cache)?)?;
+        fs::rename(temporary, self.dir.join(RESULTS))?;
+        Ok(())
+    }
+}
+
+/// Get the digest of a resolved schema with given `identity` (see `schema_identity`) at `base_uri`, covering the content of all schemas it references via `$ref` transitively and the custom meta-schema, if any.
+pub(crate) fn schema_digest(
+    identity: &str,
+    json: &Value,
+    base_uri: Option<&Url>,
+    meta_schema: Option<&Value>,
+    base: &Path,
+    retriever: &SchemaRetriever,
+) -> Result<String, Box<dyn Error>> {
+    let mut refs = Lockfile::default();
+    refs.insert_refs(json, base_uri, base, retriever)?;
+    if let Some(meta_schema) = meta_schema {
+        refs.insert("meta-schema".to_string(), meta_schema);
+    }
+    Ok(sha256_hex(
+        format!("{identity}\n{}", refs.digest()).as_bytes(),
+    ))
+}
+
+/// Get the key of the result of validating an instance with `content` against the schema with `digest` (see [`schema_digest`]), parsed as JSONC if `jsonc`.
+pub(crate) fn result_key(digest: &str, content: &[u8], jsonc: bool) -> String {
+    sha256_hex(
+        format!(
+            "{}\n{digest}\n{jsonc}\n{}",
+            env!("CARGO_PKG_VERSION"),
+            sha256_hex(content)
+        )
+        .as_bytes(),
+    )
+}
+
+/// Remove the cache directory, returning whether there was one.
+pub(crate) fn clean(dir: &Path) -> Result<bool, Box<dyn Error>> {
+    if !dir.exists() {
+        return Ok(false);
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::temp_dir;
+
+    #[test]
+    fn test_cache() {
+        let temp = temp_dir();
+        let dir = temp.path().join("cache");
+        let digest = sha256_hex(b"schema");
+        let valid = result_key(&digest, b"{}", false);
+        let invalid = result_key(&digest, b"[]", false);
+        assert_ne!(valid, invalid);
+        // Content that is only valid JSONC must not share results with strict JSON
+        assert_ne!(valid, result_key(&digest, b"{}", true));
+
+        let cache = Cache::load(&dir);
+        assert_eq!(cache.get(&valid), None);
+        cache.insert(valid.clone(), Vec::new());
+        cache.insert(invalid.clone(), vec!["[] is not of type \"object\"".into()]);
+        cache.save(true).unwrap();
+
+        let cache = Cache::load(&dir);
+        assert_eq!(cache.get(&valid), Some(&[][..]));
+        assert_eq!(
+            cache.get(&invalid),
+            Some(&["[] is not of type \"object\"".to_string()][..])
+        );
+        cache.save(true).unwrap();
+
+        // Results not used are kept unless evicting
+        let cache = Cache::load(&dir);
+        assert!(cache.get(&valid).is_some());
+        cache.save(false).unwrap();
+        let cache = Cache::load(&dir);
+        assert!(cache.get(&valid).is_some());
+        cache.save(true).unwrap();
+        let cache = Cache::load(&dir);
+        assert!(cache.get(&valid).is_some());
+        assert_eq!(cache.get(&invalid), None);
+        assert!(clean(&dir).unwrap());
+        assert!(!clean(&dir).unwrap());
+    }
+}
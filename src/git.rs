@@ -0,0 +1,342 @@
+//! Git integration: Find files changed in the repository containing the base directory, and read files from the index, using the `git` command.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::Mutex,
+};
+
+/// Run `git` with `args` in `dir`, returning its standard output.
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|error| format!("Failed to run `git`: {error}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("`git {}` failed: {}", args.join(" "), stderr.trim()).into());
+    }
+    Ok(output.stdout)
+}
+
+/// Get the canonicalized path to the top-level directory of the repository containing `dir`.
+fn toplevel(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let stdout = git(dir, &["rev-parse", "--show-toplevel"])?;
+    let toplevel = String::from_utf8(stdout)?;
+    Ok(Path::new(toplevel.trim_end()).canonicalize()?)
+}
+
+/// Split NUL-separated paths relative to `toplevel` in `stdout`, joining them to `toplevel`.
+fn split_paths<'a>(stdout: &'a [u8], toplevel: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+    stdout
+        .split(|&byte| byte == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| toplevel.join(String::from_utf8_lossy(path).as_ref()))
+}
+
+/// Resolve `rev` to diff against. In a repository without commits, `HEAD` doesn't resolve, so the empty tree is used instead, and all files are considered changed.
+fn diff_base(toplevel: &Path, rev: &str) -> Result<String, Box<dyn Error>> {
+    if rev != "HEAD" || git(toplevel, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok() {
+        return Ok(rev.to_string());
+    }
+    // Hashing empty input from the null standard input gives the ID of the empty tree, in whatever object format the repository uses
+    let stdout = git(toplevel, &["hash-object", "-t", "tree", "--stdin"])?;
+    Ok(String::from_utf8(stdout)?.trim_end().to_string())
+}
+
+/// Get canonicalized paths to files changed relative to `rev`. If `staged`, only changes in the index are considered. Otherwise, changes in the working tree are considered, staged or not, including untracked files that aren't ignored. Deleted files are included as well.
+pub(crate) fn changed_files(
+    dir: &Path,
+    rev: &str,
+    staged: bool,
+) -> Result<HashSet<PathBuf>, Box<dyn Error>> {
+    let toplevel = toplevel(dir)?;
+    let rev = &diff_base(&toplevel, rev)?;
+    if staged {
+        let diff = git(
+            &toplevel,
+            &[
+                "diff",
+                "--cached",
+                "--name-only",
+                "--no-renames",
+                "-z",
+                rev,
+                "--",
+            ],
+        )?;
+        return Ok(split_paths(&diff, &toplevel).collect());
+    }
+    let diff = git(
+        &toplevel,
+        &["diff", "--name-only", "--no-renames", "-z", rev, "--"],
+    )?;
+    let untracked = git(
+        &toplevel,
+        &["ls-files", "--others", "--exclude-standard", "-z"],
+    )?;
+    Ok(split_paths(&diff, &toplevel)
+        .chain(split_paths(&untracked, &toplevel))
+        .collect())
+}
+
+/// A running `git cat-file --batch` process, reading objects on request.
+struct Batch {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Batch {
+    /// Start reading objects of the repository at `toplevel`.
+    fn spawn(toplevel: &Path) -> io::Result<Self> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(toplevel)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("Stdin should be piped");
+        let stdout = BufReader::new(child.stdout.take().expect("Stdout should be piped"));
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Read the content of the blob with given object id.
+    fn read(&mut self, oid: &str) -> io::Result<Vec<u8>> {
+        writeln!(self.stdin, "{oid}")?;
+        self.stdin.flush()?;
+        // Header is `<oid> <type> <size>`, or `<oid> missing`
+        let mut header = String::new();
+        self.stdout.read_line(&mut header)?;
+        let size = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [_, "blob", size] => size.parse::<usize>().map_err(io::Error::other)?,
+            _ => {
+                return Err(io::Error::other(format!(
+                    "Unexpected object `{}`",
+                    header.trim_end()
+                )))
+            }
+        };
+        // Content is followed by a newline
+        let mut content = vec![0; size + 1];
+        self.stdout.read_exact(&mut content)?;
+        content.pop();
+        Ok(content)
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        // The process would wait for further requests otherwise
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A snapshot of the index of the repository containing the base directory, so that exactly what will be committed is validated.
+pub(crate) struct Staged {
+    /// Canonicalized path to the top-level directory of the repository.
+    toplevel: PathBuf,
+    /// Map from path relative to `toplevel` to the object id of regular files in the index.
+    objects: HashMap<PathBuf, String>,
+    /// Process reading objects, started on first use.
+    batch: Mutex<Option<Batch>>,
+}
+
+impl Staged {
+    /// Load the index of the repository containing `dir`.
+    pub(crate) fn load(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let toplevel = toplevel(dir)?;
+        let stdout = git(&toplevel, &["ls-files", "--stage", "-z"])?;
+        let mut objects = HashMap::new();
+        for entry in stdout.split(|&byte| byte == 0) {
+            // Entries are `<mode> <oid> <stage>\t<path>`
+            let entry = String::from_utf8_lossy(entry);
+            let Some((info, path)) = entry.split_once('\t') else {
+                continue;
+            };
+            let mut info = info.split(' ');
+            let (Some(mode), Some(oid)) = (info.next(), info.next()) else {
+                continue;
+            };
+            // Skip symbolic links and submodules
+            if mode == "100644" || mode == "100755" {
+                objects.insert(PathBuf::from(path), oid.to_string());
+            }
+        }
+        Ok(Self {
+            toplevel,
+            objects,
+            batch: Mutex::new(None),
+        })
+    }
+
+    /// Get the path relative to the top-level directory of `path`, which might not exist in the working tree, or `None` if it's outside of the repository.
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let absolute = path
+            .canonicalize()
+            .or_else(|_| {
+                let parent = path
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty());
+                let parent = parent.unwrap_or(Path::new(".")).canonicalize()?;
+                Ok::<_, io::Error>(parent.join(path.file_name().unwrap_or_default()))
+            })
+            .unwrap_or_else(|_| {
+                // Normalize lexically as a last resort
+                let absolute = env::current_dir().unwrap_or_default().join(path);
+                absolute
+                    .components()
+                    .filter(|component| *component != Component::CurDir)
+                    .collect()
+            });
+        absolute
+            .strip_prefix(&self.toplevel)
+            .ok()
+            .map(Path::to_path_buf)
+    }
+
+    /// Check whether a regular file at `path` is in the index.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.relative(path)
+            .is_some_and(|relative| self.objects.contains_key(&relative))
+    }
+
+    /// Iterate over canonicalized paths to regular files in the index under `dir`, or `None` if `dir` is outside of the repository.
+    pub(crate) fn files_under(&self, dir: &Path) -> Option<impl Iterator<Item = PathBuf> + '_> {
+        let dir = self.relative(dir)?;
+        Some(
+            self.objects
+                .keys()
+                .filter(move |path| path.starts_with(&dir))
+                .map(|path| self.toplevel.join(path)),
+        )
+    }
+
+    /// Read the content of `path` from the index, or `None` if it's outside of the repository.
+    pub(crate) fn read(&self, path: &Path) -> Option<io::Result<Vec<u8>>> {
+        let relative = self.relative(path)?;
+        let Some(oid) = self.objects.get(&relative) else {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` is not staged", relative.to_string_lossy()),
+            )));
+        };
+        let mut batch = self.batch.lock().expect("Batch lock poisoned");
+        if batch.is_none() {
+            *batch = Some(match Batch::spawn(&self.toplevel) {
+                Ok(spawned) => spawned,
+                Err(error) => return Some(Err(error)),
+            });
+        }
+        let content = batch.as_mut().expect("Batch should be started").read(oid);
+        if content.is_err() {
+            // Start over on next read, in case the process is broken
+            *batch = None;
+        }
+        Some(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::temp_dir;
+    use std::{env, fs};
+    use tempfile::TempDir;
+
+    /// Create a repository in a temporary directory, with `a.json`, `b.json` and `data/c.json` committed. Returns the directory, and its canonicalized path.
+    fn init_repository() -> (TempDir, PathBuf) {
+        let temp = temp_dir();
+        fs::create_dir_all(temp.path().join("data")).unwrap();
+        let dir = temp.path().canonicalize().unwrap();
+        for file in ["a.json", "b.json", "data/c.json"] {
+            fs::write(dir.join(file), "{}").unwrap();
+        }
+        git(&dir, &["init", "-q"]).unwrap();
+        git(&dir, &["add", "."]).unwrap();
+        git(
+            &dir,
+            &[
+                "-c",
+                "user.name=scheval",
+                "-c",
+                "user.email=scheval@example.com",
+                "commit",
+                "-qm",
+                "Initial commit",
+            ],
+        )
+        .unwrap();
+        // Modify `a.json` without staging, stage the modification of `data/c.json`, remove `b.json` and add untracked `data/d.json`
+        fs::write(dir.join("a.json"), "[]").unwrap();
+        fs::write(dir.join("data/c.json"), "[]").unwrap();
+        git(&dir, &["add", "data/c.json"]).unwrap();
+        fs::remove_file(dir.join("b.json")).unwrap();
+        fs::write(dir.join("data/d.json"), "{}").unwrap();
+        (temp, dir)
+    }
+
+    #[test]
+    fn test_changed_files() {
+        let (_temp, dir) = init_repository();
+        let changed = changed_files(&dir.join("data"), "HEAD", false).unwrap();
+        let expected: HashSet<_> = ["a.json", "b.json", "data/c.json", "data/d.json"]
+            .iter()
+            .map(|file| dir.join(file))
+            .collect();
+        assert_eq!(changed, expected);
+        let staged = changed_files(&dir, "HEAD", true).unwrap();
+        assert_eq!(staged, HashSet::from([dir.join("data/c.json")]));
+        assert!(changed_files(&dir, "no-such-rev", false).is_err());
+    }
+
+    #[test]
+    fn test_unborn_head() {
+        let temp = temp_dir();
+        let dir = temp.path().canonicalize().unwrap();
+        fs::write(dir.join("a.json"), "{}").unwrap();
+        fs::write(dir.join("b.json"), "{}").unwrap();
+        git(&dir, &["init", "-q"]).unwrap();
+        git(&dir, &["add", "a.json"]).unwrap();
+        // Without commits, every staged file is changed
+        let staged = changed_files(&dir, "HEAD", true).unwrap();
+        assert_eq!(staged, HashSet::from([dir.join("a.json")]));
+        let changed = changed_files(&dir, "HEAD", false).unwrap();
+        assert_eq!(
+            changed,
+            HashSet::from([dir.join("a.json"), dir.join("b.json")])
+        );
+        assert!(changed_files(&dir, "no-such-rev", true).is_err());
+    }
+
+    #[test]
+    fn test_staged() {
+        let (_temp, dir) = init_repository();
+        let staged = Staged::load(&dir).unwrap();
+        // Unstaged modifications and removals are invisible
+        assert_eq!(staged.read(&dir.join("a.json")).unwrap().unwrap(), b"{}");
+        assert_eq!(staged.read(&dir.join("b.json")).unwrap().unwrap(), b"{}");
+        assert_eq!(
+            staged.read(&dir.join("data/c.json")).unwrap().unwrap(),
+            b"[]"
+        );
+        // Untracked files are not staged, and files outside of the repository are not covered
+        assert!(!staged.contains(&dir.join("data/d.json")));
+        assert!(staged.read(&dir.join("data/d.json")).unwrap().is_err());
+        assert!(staged.read(&env::temp_dir()).is_none());
+        let files: Vec<_> = staged.files_under(&dir.join("data")).unwrap().collect();
+        assert_eq!(files, [dir.join("data/c.json")]);
+    }
+}
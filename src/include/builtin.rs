@@ -0,0 +1,132 @@
+//! Built-in auto detection: Validate well-known config files with schemas bundled into the binary, without network access.
+
+use super::{FileIndex, Include, Schema};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// A schema bundled into the binary.
+struct BuiltinSchema {
+    /// Name identifying the schema.
+    name: &'static str,
+    /// Glob patterns of files to validate with the schema, in `fileMatch` syntax.
+    file_match: &'static [&'static str],
+    /// The schema itself.
+    content: &'static str,
+}
+
+/// All schemas bundled into the binary.
+const BUILTIN_SCHEMAS: &[BuiltinSchema] = &[
+    BuiltinSchema {
+        name: "package.json",
+        file_match: &["package.json", "!node_modules"],
+        content: include_str!("builtin/package.schema.json"),
+    },
+    BuiltinSchema {
+        name: "tsconfig.json",
+        file_match: &["tsconfig.json", "tsconfig.*.json", "jsconfig.json"],
+        content: include_str!("builtin/tsconfig.schema.json"),
+    },
+    BuiltinSchema {
+        name: "devcontainer.json",
+        file_match: &[
+            ".devcontainer.json",
+            "/.devcontainer/devcontainer.json",
+            "/.devcontainer/*/devcontainer.json",
+        ],
+        content: include_str!("builtin/devcontainer.schema.json"),
+    },
+    BuiltinSchema {
+        name: "vscode-extensions.json",
+        file_match: &["/.vscode/extensions.json"],
+        content: include_str!("builtin/vscode-extensions.schema.json"),
+    },
+    BuiltinSchema {
+        name: "rust-project.json",
+        file_match: &["rust-project.json"],
+        content: include_str!("builtin/rust-project.schema.json"),
+    },
+];
+
+/// Get the built-in schema with given name.
+pub(crate) fn get_builtin_schema(name: &str) -> Option<Value> {
+    BUILTIN_SCHEMAS
+        .iter()
+        .find(|schema| schema.name == name)
+        .map(|schema| serde_json::from_str(schema.content).expect("Built-in schema is valid JSON"))
+}
+
+/// A smart including feature of scheval, capable of validating well-known config files with schemas bundled into the binary.
+pub struct Builtin {
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
+}
+
+impl Include for Builtin {
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self { index }
+    }
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        let mut associations = BTreeMap::new();
+        for schema in BUILTIN_SCHEMAS {
+            let instances = self.index.matching(schema.file_match);
+            if instances.is_empty() {
+                continue;
+            }
+            associations.insert(Schema::Builtin(schema.name.to_string()), instances);
+        }
+        associations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::{btreeset_of_pathbuf, temp_dir, TEST_DIR};
+    use std::fs;
+
+    #[test]
+    fn test_builtin() {
+        let inc = Builtin::with_base(TEST_DIR);
+        let associations = inc.get_associations();
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [
+            (
+                Schema::Builtin("package.json".to_string()),
+                btreeset_of_pathbuf(&["extension/package.json"]),
+            ),
+            (
+                Schema::Builtin("vscode-extensions.json".to_string()),
+                btreeset_of_pathbuf(&[".vscode/extensions.json"]),
+            ),
+        ]
+        .into();
+        assert_eq!(associations, expected);
+        // All built-in schemas should be valid
+        for schema in BUILTIN_SCHEMAS {
+            let schema = get_builtin_schema(schema.name).unwrap();
+            assert!(jsonschema::validator_for(&schema).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_node_modules() {
+        let dir = temp_dir();
+        let base = dir.path();
+        fs::create_dir_all(base.join("node_modules/dep")).unwrap();
+        fs::write(base.join("package.json"), "{}").unwrap();
+        fs::write(base.join("node_modules/dep/package.json"), "{}").unwrap();
+        let inc = Builtin::with_base(base.to_str().unwrap());
+        // Installed dependencies aren't validated
+        assert_eq!(
+            inc.get_associations(),
+            [(
+                Schema::Builtin("package.json".to_string()),
+                btreeset_of_pathbuf(&["package.json"]),
+            )]
+            .into()
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Catalog auto detection: Associate well-known files with schemas listed in a SchemaStore-format `catalog.json`
+// https://github.com/SchemaStore/schemastore/blob/master/src/api/json/catalog.json
+
+use super::{
+    vscode::{get_patterns, get_schema_from_url},
+    FileIndex, Include, Schema,
+};
+use crate::read_json;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A curated snapshot of the SchemaStore catalog, bundled into the binary.
+#[cfg(feature = "bundled-catalog")]
+const BUNDLED_CATALOG: &str = include_str!("catalog.json");
+
+/// Extensions of files that might be matched by a catalog entry, but can't be parsed as JSON. Files without an extension (like `.prettierrc`) are skipped as well, since they are frequently YAML or JSON5.
+const NON_JSON_EXTENSIONS: [&str; 3] = ["yml", "yaml", "toml"];
+
+/// A smart including feature of scheval, capable of associating files with schemas listed in a SchemaStore-format `catalog.json`.
+pub struct Catalog {
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
+    /// Path to the catalog file. If `None`, the bundled snapshot is used if available.
+    catalog: Option<PathBuf>,
+}
+
+impl Catalog {
+    /// Use the catalog file at `catalog` instead of the bundled snapshot.
+    pub fn with_catalog(mut self, catalog: &Path) -> Self {
+        self.catalog = Some(catalog.to_path_buf());
+        self
+    }
+}
+
+/// Read the `schemas` field from the catalog, falling back to the bundled snapshot if no path is given
+fn read_catalog_entries(catalog: Option<&Path>) -> Option<Vec<Value>> {
+    let catalog = match catalog {
+        Some(path) => {
+            let Ok(Ok(catalog)) = read_json(path, None) else {
+                eprintln!("Failed to read catalog `{}`", path.to_string_lossy());
+                return None;
+            };
+            catalog
+        }
+        #[cfg(feature = "bundled-catalog")]
+        None => serde_json::from_str(BUNDLED_CATALOG).expect("Bundled catalog is valid JSON"),
+        #[cfg(not(feature = "bundled-catalog"))]
+        None => return None,
+    };
+    let Some(Value::Array(entries)) = catalog.get("schemas") else {
+        eprintln!("`schemas` field not found in catalog, or is not an array");
+        return None;
+    };
+    Some(entries.to_vec())
+}
+
+impl Include for Catalog {
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self {
+            index,
+            catalog: None,
+        }
+    }
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        let base = self.index.base();
+        let Some(entries) = read_catalog_entries(self.catalog.as_deref()) else {
+            return BTreeMap::new();
+        };
+        let catalog_dir = self
+            .catalog
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."));
+        let mut associations = BTreeMap::new();
+        for entry in entries {
+            // Entries without `fileMatch` can't be associated automatically
+            let Some(Value::Array(file_match)) = entry.get("fileMatch") else {
+                continue;
+            };
+            let Some(Value::String(url)) = entry.get("url") else {
+                eprintln!("`url` field not found in catalog entry, or is not a string");
+                continue;
+            };
+            let patterns = get_patterns(file_match);
+            let instances = self
+                .index
+                .matching(&patterns)
+                .into_iter()
+                .filter(|instance| {
+                    instance
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| !NON_JSON_EXTENSIONS.contains(&ext))
+                })
+                .collect::<BTreeSet<_>>();
+            if instances.is_empty() {
+                continue;
+            }
+            let Some(schema) = get_schema_from_url(url, catalog_dir, base) else {
+                eprintln!("Failed to get schema from catalog entry");
+                continue;
+            };
+            associations
+                .entry(schema)
+                .or_insert_with(BTreeSet::new)
+                .extend(instances);
+        }
+        associations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::{btreeset_of_pathbuf, TEST_DIR};
+
+    #[test]
+    fn test_catalog() {
+        let catalog = Path::new(TEST_DIR).join("catalog/catalog.json");
+        let inc = Catalog::with_base(TEST_DIR).with_catalog(&catalog);
+        let associations = inc.get_associations();
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [(
+            Schema::Local(PathBuf::from("receipts.schema.json")),
+            btreeset_of_pathbuf(&["receipts/1.json", "receipts/2.json"]),
+        )]
+        .into();
+        assert_eq!(associations, expected);
+    }
+
+    #[cfg(feature = "bundled-catalog")]
+    #[test]
+    fn test_bundled_catalog() {
+        let dir = crate::tests_util::temp_dir();
+        let base = dir.path();
+        std::fs::create_dir_all(base.join("node_modules/dep")).unwrap();
+        std::fs::write(base.join("package.json"), "{}").unwrap();
+        std::fs::write(base.join("node_modules/dep/package.json"), "{}").unwrap();
+        let inc = Catalog::with_base(base.to_str().unwrap());
+        // Installed dependencies aren't validated
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [(
+            Schema::Remote("https://json.schemastore.org/package.json".to_string()),
+            btreeset_of_pathbuf(&["package.json"]),
+        )]
+        .into();
+        assert_eq!(inc.get_associations(), expected);
+        // Every bundled entry matches JSON files
+        for entry in read_catalog_entries(None).unwrap() {
+            let Some(Value::Array(file_match)) = entry.get("fileMatch") else {
+                continue;
+            };
+            assert!(
+                get_patterns(file_match).iter().any(|pattern| {
+                    !pattern.starts_with('!')
+                        && !NON_JSON_EXTENSIONS
+                            .iter()
+                            .any(|ext| pattern.ends_with(&format!(".{ext}")))
+                }),
+                "{entry}"
+            );
+        }
+    }
+}
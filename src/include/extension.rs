@@ -0,0 +1,126 @@
+//! Extension auto detection: Respect `contributes.jsonValidation` field at `package.json` of VSCode extensions in the workspace
+// https://code.visualstudio.com/api/references/contribution-points#contributes.jsonValidation
+
+use super::{
+    vscode::{get_patterns, get_schema_from_url},
+    FileIndex, Include, Schema,
+};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    slice,
+    sync::Arc,
+};
+
+/// A smart including feature of scheval, capable of respecting `contributes.jsonValidation` field at `package.json` of VSCode extensions in the workspace.
+pub struct Extension {
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
+}
+
+/// Read `contributes.jsonValidation` field from `package.json` at `manifest` via `index`
+fn read_json_validation(manifest: &Path, index: &FileIndex) -> Option<Vec<Value>> {
+    let Ok(Ok(package)) = index
+        .read(manifest)
+        .map(|content| serde_json::from_slice::<Value>(&content))
+    else {
+        eprintln!("Failed to read `{}`", manifest.to_string_lossy());
+        return None;
+    };
+    // Most packages are not extensions, so no need to warn
+    let json_validation = package.get("contributes")?.get("jsonValidation")?;
+    let Value::Array(association_definitions) = json_validation else {
+        eprintln!("`contributes.jsonValidation` field is not an array");
+        return None;
+    };
+    Some(association_definitions.to_vec())
+}
+
+/// Check whether `package.json` at `manifest` declares `contributes.jsonValidation`, without warning if it can't be read.
+pub(crate) fn declares_json_validation(manifest: &Path, index: &FileIndex) -> bool {
+    index
+        .read(manifest)
+        .ok()
+        .and_then(|content| serde_json::from_slice::<Value>(&content).ok())
+        .is_some_and(|package| package.pointer("/contributes/jsonValidation").is_some())
+}
+
+impl Include for Extension {
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self { index }
+    }
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        let base = self.index.base();
+        let manifests = self.index.matching(&["package.json", "!node_modules"]);
+        let mut associations = BTreeMap::new();
+        for manifest in manifests {
+            let manifest = base.join(manifest);
+            let Some(association_definitions) = read_json_validation(&manifest, &self.index) else {
+                continue;
+            };
+            let extension_dir = manifest.parent().unwrap_or(base);
+            for association_definition in association_definitions {
+                // Unwrap the `fileMatch` field (glob pattern or array of glob patterns)
+                let patterns = match association_definition.get("fileMatch") {
+                    Some(Value::Array(file_match)) => get_patterns(file_match),
+                    Some(file_match @ Value::String(_)) => {
+                        get_patterns(slice::from_ref(file_match))
+                    }
+                    Some(_) => {
+                        eprintln!("`fileMatch` field is neither a string nor an array");
+                        continue;
+                    }
+                    None => {
+                        eprintln!("`fileMatch` field not found in `jsonValidation`");
+                        continue;
+                    }
+                };
+
+                // Unwrap the `url` field, relative to the extension
+                let Some(Value::String(url)) = association_definition.get("url") else {
+                    eprintln!("`url` field not found in `jsonValidation`, or is not a string");
+                    continue;
+                };
+                let Some(schema) = get_schema_from_url(url, extension_dir, base) else {
+                    eprintln!("Failed to get schema from `jsonValidation`");
+                    continue;
+                };
+
+                // Collect instances
+                let instances = self.index.matching(&patterns);
+
+                // Update associations
+                associations
+                    .entry(schema)
+                    .or_insert_with(BTreeSet::new)
+                    .extend(instances);
+            }
+        }
+        associations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::{btreeset_of_pathbuf, TEST_DIR};
+
+    #[test]
+    fn test_extension() {
+        let inc = Extension::with_base(TEST_DIR);
+        let associations = inc.get_associations();
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [
+            (
+                Schema::Local(PathBuf::from("extension/schemas/ext.schema.json")),
+                btreeset_of_pathbuf(&["extension/sample.ext.json"]),
+            ),
+            (
+                Schema::Local(PathBuf::from("receipts.schema.json")),
+                btreeset_of_pathbuf(&["receipts.json"]),
+            ),
+        ]
+        .into();
+        assert_eq!(associations, expected);
+    }
+}
@@ -0,0 +1,323 @@
+//! File index: Walk the base directory once, so that all including features can query the same listing.
+
+use crate::{git::Staged, read_file, regularize};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    overrides::OverrideBuilder,
+    Match,
+};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{self, Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
+use walkdir::WalkDir;
+
+/// An entry in the file index.
+struct Entry {
+    /// Path relative to the base directory.
+    path: PathBuf,
+    /// Whether the entry is a directory.
+    is_dir: bool,
+}
+
+/// A listing of all files and directories under a base directory, built on first use and shared by all including features.
+pub struct FileIndex {
+    /// Canonicalized path to the base directory.
+    base: PathBuf,
+    /// Gitignore-style patterns of paths to skip.
+    excludes: Gitignore,
+    /// Entries in walk order, so that the contents of a directory directly follow it.
+    entries: OnceLock<Vec<Entry>>,
+    /// Snapshot of the git index to list and read files from instead of the working tree, if any.
+    staged: Option<Arc<Staged>>,
+}
+
+impl FileIndex {
+    /// Create an index of the base directory.
+    pub fn new(base: &str) -> Self {
+        Self::with_excludes(base, &[])
+    }
+
+    /// Create an index of the base directory, skipping paths matching any of the gitignore-style `excludes`.
+    pub fn with_excludes(base: &str, excludes: &[String]) -> Self {
+        let base = Path::new(base)
+            .canonicalize()
+            .expect("Failed to canonicalize base directory");
+        let mut builder = GitignoreBuilder::new(&base);
+        for exclude in excludes {
+            if let Err(error) = builder.add_line(None, exclude) {
+                eprintln!("Invalid exclude pattern `{exclude}`: {error}");
+            }
+        }
+        let excludes = builder.build().unwrap_or_else(|error| {
+            eprintln!("Failed to build exclude patterns: {error}");
+            Gitignore::empty()
+        });
+        Self {
+            base,
+            excludes,
+            entries: OnceLock::new(),
+            staged: None,
+        }
+    }
+
+    /// List and read files from a snapshot of the git index instead of the working tree.
+    pub(crate) fn with_staged(mut self, staged: Arc<Staged>) -> Self {
+        self.staged = Some(staged);
+        self
+    }
+
+    /// Snapshot of the git index files are listed and read from, if any.
+    pub(crate) fn staged(&self) -> Option<&Arc<Staged>> {
+        self.staged.as_ref()
+    }
+
+    /// Check whether a file exists at `path`, in the git index if listing from it.
+    pub(crate) fn exists(&self, path: &Path) -> bool {
+        match &self.staged {
+            Some(staged) => staged.contains(path),
+            None => path.exists(),
+        }
+    }
+
+    /// Read the file at `path`, from the git index if listing from it.
+    pub(crate) fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        read_file(path, self.staged.as_deref())
+    }
+
+    /// Read the file at `path` as a string, from the git index if listing from it.
+    pub(crate) fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Canonicalized path to the base directory.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Get the entries, walking the base directory (or listing the git index) if not done yet.
+    fn entries(&self) -> &[Entry] {
+        self.entries.get_or_init(|| {
+            if let Some(entries) = self
+                .staged
+                .as_ref()
+                .and_then(|staged| self.staged_entries(staged))
+            {
+                return entries;
+            }
+            let mut entries = Vec::new();
+            let mut walker = WalkDir::new(&self.base).min_depth(1).into_iter();
+            while let Some(item) = walker.next() {
+                let Ok(item) = item else {
+                    eprintln!("Failed to read item: {}", item.unwrap_err());
+                    continue;
+                };
+                let file_type = item.file_type();
+                let is_dir = file_type.is_dir();
+                let path = regularize(&self.base, item.path());
+                if self.excludes.matched(&path, is_dir).is_ignore() {
+                    if is_dir {
+                        walker.skip_current_dir();
+                    }
+                    continue;
+                }
+                // Symbolic links to files are kept, while those to directories aren't followed
+                let is_file = file_type.is_file()
+                    || file_type.is_symlink()
+                        && fs::metadata(item.path()).is_ok_and(|m| m.is_file());
+                if is_dir || is_file {
+                    entries.push(Entry { path, is_dir });
+                }
+            }
+            entries
+        })
+    }
+
+    /// List entries from the git index, with directories derived from paths to files, or `None` if the base directory is outside of the repository.
+    fn staged_entries(&self, staged: &Staged) -> Option<Vec<Entry>> {
+        let mut paths = BTreeSet::new();
+        for file in staged.files_under(&self.base)? {
+            let path = regularize(&self.base, &file);
+            if self.is_excluded(&path) {
+                continue;
+            }
+            paths.extend(
+                path.ancestors()
+                    .skip(1)
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .map(|dir| (dir.to_path_buf(), true)),
+            );
+            paths.insert((path, false));
+        }
+        // Paths are ordered by components, so that the contents of a directory directly follow it
+        Some(
+            paths
+                .into_iter()
+                .map(|(path, is_dir)| Entry { path, is_dir })
+                .collect(),
+        )
+    }
+
+    /// Check whether `path`, relative to the base directory, or any of its parents is excluded.
+    pub(crate) fn is_excluded(&self, path: &Path) -> bool {
+        self.excludes
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+    }
+
+    /// Iterate over all files, relative to the base directory.
+    pub(crate) fn files(&self) -> impl Iterator<Item = &Path> {
+        self.entries()
+            .iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| entry.path.as_path())
+    }
+
+    /// Find JSON files under `dir`, as sorted absolute paths. Directories inside the base directory are looked up in this index, so that excludes apply, while others are walked separately.
+    pub(crate) fn json_files_under(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        // Directories might only exist in the git index
+        let dir = match (dir.canonicalize(), &self.staged) {
+            (Err(_), Some(_)) => path::absolute(dir)?,
+            (dir, _) => dir?,
+        };
+        let is_json = |path: &&Path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        };
+        let mut files: Vec<_> = match dir.strip_prefix(&self.base) {
+            Ok(relative) => self
+                .files()
+                .filter(|file| file.starts_with(relative))
+                .filter(is_json)
+                .map(|file| self.base.join(file))
+                .collect(),
+            Err(_) => {
+                let index = Self {
+                    base: dir,
+                    excludes: Gitignore::empty(),
+                    entries: OnceLock::new(),
+                    staged: self.staged.clone(),
+                };
+                index
+                    .files()
+                    .filter(is_json)
+                    .map(|file| index.base.join(file))
+                    .collect()
+            }
+        };
+        files.sort();
+        Ok(files)
+    }
+
+    /// Find files matching given glob patterns, relative to the base directory. Directories matching a negated pattern are skipped entirely, like `GlobWalker` does.
+    pub(crate) fn matching<S: AsRef<str>>(&self, patterns: &[S]) -> BTreeSet<PathBuf> {
+        let mut builder = OverrideBuilder::new(&self.base);
+        for pattern in patterns {
+            // A single asterisk would match paths inside sub-directories too
+            let pattern = match pattern.as_ref() {
+                "*" => "/*",
+                pattern => pattern,
+            };
+            if let Err(error) = builder.add(pattern) {
+                eprintln!("Invalid pattern `{pattern}`: {error}");
+            }
+        }
+        let Ok(overrides) = builder.build() else {
+            eprintln!("Failed to build patterns");
+            return BTreeSet::new();
+        };
+
+        let mut instances = BTreeSet::new();
+        let mut skipped: Option<&Path> = None;
+        for entry in self.entries() {
+            if let Some(dir) = skipped {
+                if entry.path.starts_with(dir) {
+                    continue;
+                }
+                skipped = None;
+            }
+            match overrides.matched(&entry.path, entry.is_dir) {
+                Match::Whitelist(_) if !entry.is_dir => {
+                    instances.insert(entry.path.clone());
+                }
+                Match::Ignore(_) if entry.is_dir => skipped = Some(&entry.path),
+                _ => {}
+            }
+        }
+        instances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::{btreeset_of_pathbuf, temp_dir, TEST_DIR};
+
+    #[test]
+    fn test_matching() {
+        let index = FileIndex::new(TEST_DIR);
+        assert_eq!(
+            index.matching(&["/*.json", "!*.schema.json"]),
+            btreeset_of_pathbuf(&["foo_schema.json", "legacy.json", "receipts.json"])
+        );
+        assert_eq!(
+            index.matching(&["receipts/*.json", "!receipts/0.*"]),
+            btreeset_of_pathbuf(&["receipts/1.json", "receipts/2.json"])
+        );
+    }
+
+    #[test]
+    fn test_json_files_under() {
+        let excludes = ["receipts/0.*".to_string()];
+        let index = FileIndex::with_excludes(TEST_DIR, &excludes);
+        let base = Path::new(TEST_DIR).canonicalize().unwrap();
+        // Excludes apply inside of the base directory
+        assert_eq!(
+            index
+                .json_files_under(&Path::new(TEST_DIR).join("receipts"))
+                .unwrap(),
+            [base.join("receipts/1.json"), base.join("receipts/2.json")]
+        );
+        // Directories outside of the base directory are walked separately
+        let index = FileIndex::new(&format!("{TEST_DIR}/receipts"));
+        assert_eq!(
+            index
+                .json_files_under(&Path::new(TEST_DIR).join("registry"))
+                .unwrap(),
+            [base.join("registry/address.json")]
+        );
+        assert!(index.json_files_under(Path::new("no-such-dir")).is_err());
+    }
+
+    #[test]
+    fn test_excludes() {
+        let excludes = ["receipts/".to_string(), "*.schema.json".to_string()];
+        let index = FileIndex::with_excludes(TEST_DIR, &excludes);
+        assert_eq!(
+            index.matching(&["/*.json", "receipts/*.json"]),
+            btreeset_of_pathbuf(&["foo_schema.json", "legacy.json", "receipts.json"])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = temp_dir();
+        let base = dir.path();
+        fs::create_dir(base.join("sub")).unwrap();
+        fs::write(base.join("sub/a.json"), "{}").unwrap();
+        symlink(base.join("sub/a.json"), base.join("b.json")).unwrap();
+        symlink(base.join("sub"), base.join("link")).unwrap();
+        symlink(base.join("missing.json"), base.join("broken.json")).unwrap();
+        let index = FileIndex::new(base.to_str().unwrap());
+        assert_eq!(
+            index.matching(&["**/*.json"]),
+            btreeset_of_pathbuf(&["b.json", "sub/a.json"])
+        );
+    }
+}
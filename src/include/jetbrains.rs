@@ -0,0 +1,207 @@
+//! JetBrains auto detection: Respect JSON Schema mappings at `.idea/jsonSchemas.xml` if present
+//!
+//! The `schemaVersion` option of mappings is ignored, so the draft is detected from `$schema` as usual. Use `--schema-draft` to override it.
+// https://www.jetbrains.com/help/idea/json.html#ws_json_schema_add_custom
+
+use super::{vscode::get_schema_from_url, Drafts, FileIndex, Include, Schema};
+use crate::lock::lock_key;
+use jsonschema::Draft;
+use roxmltree::{Document, Node};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use url::Url;
+
+/// A smart including feature of scheval, capable of respecting JSON Schema mappings at `.idea/jsonSchemas.xml` if present.
+pub struct Jetbrains {
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
+}
+
+/// Get the value of the `<option name="{name}" value="..." />` child of `node`
+fn get_option<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name("option") && child.attribute("name") == Some(name))
+        .and_then(|option| option.attribute("value"))
+}
+
+/// Convert a mapping `<Item>` into a glob pattern
+fn get_pattern(item: Node) -> Option<String> {
+    let Some(path) = get_option(item, "path") else {
+        eprintln!("`path` option not found in mapping");
+        return None;
+    };
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    if get_option(item, "pattern") == Some("true") {
+        // File path pattern, matched anywhere in the project
+        Some(path.to_string())
+    } else if get_option(item, "directory") == Some("true") {
+        // All JSON files under the directory
+        Some(format!("/{path}/**/*.json"))
+    } else {
+        // A single file relative to the project root
+        Some(format!("/{path}"))
+    }
+}
+
+/// Get the draft from the `schemaVersion` option, e.g. `JSON Schema version 7`
+fn get_draft(schema_info: Node) -> Option<Draft> {
+    let version = get_option(schema_info, "schemaVersion")?;
+    match version.rsplit(' ').next()? {
+        "4" => Some(Draft::Draft4),
+        "6" => Some(Draft::Draft6),
+        "7" => Some(Draft::Draft7),
+        "2019-09" | "2019.09" => Some(Draft::Draft201909),
+        "2020-12" | "2020.12" => Some(Draft::Draft202012),
+        _ => {
+            eprintln!("Unknown `schemaVersion` `{version}` in mapping");
+            None
+        }
+    }
+}
+
+/// Get schema from the `relativePathToSchema` option relative to `base`, which is an absolute path for schemas outside of the project
+fn get_schema(schema_info: Node, base: &Path) -> Option<Schema> {
+    let Some(schema_path) = get_option(schema_info, "relativePathToSchema") else {
+        eprintln!("`relativePathToSchema` option not found in mapping");
+        return None;
+    };
+    let schema_path = schema_path.trim_start_matches("$PROJECT_DIR$/");
+    if Path::new(schema_path).is_absolute() {
+        let Ok(url) = Url::from_file_path(schema_path) else {
+            eprintln!("Invalid schema path `{schema_path}`");
+            return None;
+        };
+        return get_schema_from_url(url.as_str(), base, base);
+    }
+    get_schema_from_url(schema_path, base, base)
+}
+
+impl Jetbrains {
+    /// Generate associations like [`Include::get_associations`], along with the drafts of mapped schemas (identified by [`lock_key`]) given by `schemaVersion`.
+    pub(crate) fn get_associations_with_drafts(
+        &self,
+    ) -> (BTreeMap<Schema, BTreeSet<PathBuf>>, Drafts) {
+        let base = self.index.base();
+        let mappings = base.join(".idea/jsonSchemas.xml");
+        if !self.index.exists(&mappings) {
+            eprintln!("No .idea/jsonSchemas.xml found");
+            return (BTreeMap::new(), Vec::new());
+        }
+        let Ok(mappings_text) = self.index.read_to_string(&mappings) else {
+            eprintln!("Failed to read .idea/jsonSchemas.xml");
+            return (BTreeMap::new(), Vec::new());
+        };
+        let Ok(document) = Document::parse(&mappings_text) else {
+            eprintln!("Failed to parse .idea/jsonSchemas.xml");
+            return (BTreeMap::new(), Vec::new());
+        };
+        let mut associations = BTreeMap::new();
+        let mut drafts = Vec::new();
+        for schema_info in document
+            .descendants()
+            .filter(|node| node.has_tag_name("SchemaInfo"))
+        {
+            // Collect patterns from `<option name="patterns"><list><Item>...</Item></list></option>`
+            let patterns = schema_info
+                .children()
+                .filter(|child| {
+                    child.has_tag_name("option") && child.attribute("name") == Some("patterns")
+                })
+                .flat_map(|option| option.descendants())
+                .filter(|node| node.has_tag_name("Item"))
+                .filter_map(get_pattern)
+                .collect::<Vec<_>>();
+            if patterns.is_empty() {
+                eprintln!("No patterns found in mapping");
+                continue;
+            }
+
+            let Some(schema) = get_schema(schema_info, base) else {
+                eprintln!("Failed to get schema from mapping");
+                continue;
+            };
+
+            if let Some(draft) = get_draft(schema_info) {
+                drafts.push((lock_key(&schema), draft));
+            }
+
+            // Collect instances
+            let instances = self.index.matching(&patterns);
+
+            // Update associations
+            associations
+                .entry(schema)
+                .or_insert_with(BTreeSet::new)
+                .extend(instances);
+        }
+        (associations, drafts)
+    }
+}
+
+impl Include for Jetbrains {
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self { index }
+    }
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        self.get_associations_with_drafts().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::{btreeset_of_pathbuf, TEST_DIR};
+
+    #[test]
+    fn test_jetbrains() {
+        let inc = Jetbrains::with_base(TEST_DIR);
+        let associations = inc.get_associations();
+        // `jetbrains/configs/README.md` isn't JSON, thus not mapped
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [
+            (
+                Schema::Local(PathBuf::from("receipts.schema.json")),
+                btreeset_of_pathbuf(&["receipts.json"]),
+            ),
+            (
+                Schema::Local(PathBuf::from("jetbrains/name.schema.json")),
+                btreeset_of_pathbuf(&[
+                    "jetbrains/configs/bar.json",
+                    "jetbrains/configs/nested/baz.json",
+                    "jetbrains/qux.jb.json",
+                ]),
+            ),
+        ]
+        .into();
+        assert_eq!(associations, expected);
+        let (_, drafts) = inc.get_associations_with_drafts();
+        assert_eq!(
+            drafts,
+            [("jetbrains/name.schema.json".to_string(), Draft::Draft7)]
+        );
+    }
+
+    #[test]
+    fn test_absolute_schema_path() {
+        let base = Path::new(TEST_DIR).canonicalize().unwrap();
+        let schema_path = base.join("receipts.schema.json");
+        let xml = format!(
+            r#"<SchemaInfo><option name="relativePathToSchema" value="{}" /></SchemaInfo>"#,
+            schema_path.to_string_lossy()
+        );
+        let document = Document::parse(&xml).unwrap();
+        let schema_info = document.root_element();
+        // Inside of the project
+        assert_eq!(
+            get_schema(schema_info, &base),
+            Some(Schema::Local(PathBuf::from("receipts.schema.json")))
+        );
+        // Outside of the project
+        assert_eq!(
+            get_schema(schema_info, &base.join("jetbrains")),
+            Some(Schema::Local(schema_path))
+        );
+    }
+}
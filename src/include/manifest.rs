@@ -0,0 +1,131 @@
+//! Manifest auto detection: Respect `schemas` field at `[package.metadata.scheval]` / `[workspace.metadata.scheval]` in `Cargo.toml` and `[tool.scheval]` in `pyproject.toml` if present
+//!
+//! The `schemas` field shares the format of `json.schemas` in `.vscode/settings.json`:
+//!
+//! ```toml
+//! [package.metadata.scheval]
+//! schemas = [
+//!     { fileMatch = ["config/*.json"], url = "./schemas/config.schema.json" },
+//! ]
+//! ```
+
+use super::{vscode::get_associations_from_definitions, FileIndex, Include, Schema};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Manifest files and the paths to the scheval configuration table in them.
+const MANIFESTS: [(&str, &[&str]); 3] = [
+    ("Cargo.toml", &["package", "metadata", "scheval"]),
+    ("Cargo.toml", &["workspace", "metadata", "scheval"]),
+    ("pyproject.toml", &["tool", "scheval"]),
+];
+
+/// A smart including feature of scheval, capable of respecting scheval configuration in `Cargo.toml` and `pyproject.toml` if present.
+pub struct Manifest {
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
+}
+
+/// Read a TOML manifest at `path` via `index` as a JSON value
+fn read_manifest(path: &Path, index: &FileIndex) -> Option<Value> {
+    let Ok(text) = index.read_to_string(path) else {
+        eprintln!("Failed to read `{}`", path.to_string_lossy());
+        return None;
+    };
+    match toml::from_str(&text) {
+        Ok(manifest) => Some(manifest),
+        Err(error) => {
+            eprintln!("Failed to parse `{}`: {error}", path.to_string_lossy());
+            None
+        }
+    }
+}
+
+/// Read `schemas` field from the table at `table_path` in `manifest`
+fn read_schema_associations(manifest: &Value, table_path: &[&str]) -> Option<Vec<Value>> {
+    let table = table_path
+        .iter()
+        .try_fold(manifest, |value, key| value.get(key))?;
+    let Some(schemas) = table.get("schemas") else {
+        eprintln!("`schemas` field not found in `{}`", table_path.join("."));
+        return None;
+    };
+    let Value::Array(association_definitions) = schemas else {
+        eprintln!("`{}.schemas` field is not an array", table_path.join("."));
+        return None;
+    };
+    Some(association_definitions.to_vec())
+}
+
+impl Include for Manifest {
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self { index }
+    }
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        let base = self.index.base();
+        let mut associations = BTreeMap::new();
+        let mut manifests = BTreeMap::new();
+        for (file_name, table_path) in MANIFESTS {
+            let path = base.join(file_name);
+            if !self.index.exists(&path) {
+                continue;
+            }
+            let Some(manifest) = manifests
+                .entry(file_name)
+                .or_insert_with(|| read_manifest(&path, &self.index))
+            else {
+                continue;
+            };
+            let Some(association_definitions) = read_schema_associations(manifest, table_path)
+            else {
+                continue;
+            };
+            for (schema, instances) in
+                get_associations_from_definitions(association_definitions, &self.index, &path)
+            {
+                associations
+                    .entry(schema)
+                    .or_insert_with(BTreeSet::new)
+                    .extend(instances);
+            }
+        }
+        associations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        retrieve::file_uri,
+        tests_util::{btreeset_of_pathbuf, TEST_DIR},
+    };
+
+    #[test]
+    fn test_manifest() {
+        let base = Path::new(TEST_DIR).join("manifest");
+        let inc = Manifest::with_base(base.to_str().unwrap());
+        let associations = inc.get_associations();
+        let pyproject_toml = base.join("pyproject.toml").canonicalize().unwrap();
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [
+            (
+                Schema::Local(PathBuf::from("schemas/name.schema.json")),
+                btreeset_of_pathbuf(&["cargo/pkg.json", "cargo/workspace.json"]),
+            ),
+            (
+                Schema::Inline(serde_json::json!({
+                    "$id": file_uri(&pyproject_toml),
+                    "type": "object",
+                    "required": ["enabled"]
+                })),
+                btreeset_of_pathbuf(&["pyproject/tool.json"]),
+            ),
+        ]
+        .into();
+        assert_eq!(associations, expected);
+    }
+}
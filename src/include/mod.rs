@@ -1,15 +1,45 @@
 //! This module contains the `Include` trait and re-exports all including features for convenience.
 
+#[cfg(feature = "builtin-schemas")]
+mod builtin;
+mod catalog;
+mod extension;
+mod index;
+mod jetbrains;
+mod manifest;
 mod suffix;
 mod vscode;
 use crate::Schema;
+#[cfg(feature = "builtin-schemas")]
+pub(crate) use builtin::get_builtin_schema;
+#[cfg(feature = "builtin-schemas")]
+pub use builtin::Builtin;
+pub use catalog::Catalog;
+pub(crate) use extension::declares_json_validation;
+pub use extension::Extension;
+pub use index::FileIndex;
+pub use jetbrains::Jetbrains;
+pub use manifest::Manifest;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet},
     path::PathBuf,
+    sync::Arc,
 };
 pub use suffix::Suffix;
 pub use vscode::Vscode;
 
+/// Drafts of schemas, identified by their key in the lockfile, given by including features.
+pub(crate) type Drafts = Vec<(String, jsonschema::Draft)>;
+
+/// Files defining associations for including features, relative to any directory.
+pub(crate) const CONFIG_FILES: [&str; 5] = [
+    ".vscode/settings.json",
+    ".idea/jsonSchemas.xml",
+    "Cargo.toml",
+    "pyproject.toml",
+    "package.json",
+];
+
 /// A smart including feature of scheval that is capable of finding JSON instances and their corresponding schemas.
 pub trait Include {
     /// Create a new instance of the including feature.
@@ -20,7 +50,14 @@ pub trait Include {
         Self::with_base(".")
     }
     /// Create a new instance of the including feature with a base directory.
-    fn with_base(base: &str) -> Self;
-    /// Generate a map from JSON schema to paths to JSON instances.
-    fn get_associations(&self) -> HashMap<Schema, HashSet<PathBuf>>;
+    fn with_base(base: &str) -> Self
+    where
+        Self: Sized,
+    {
+        Self::with_index(Arc::new(FileIndex::new(base)))
+    }
+    /// Create a new instance of the including feature with an index of the base directory, which might be shared with other including features.
+    fn with_index(index: Arc<FileIndex>) -> Self;
+    /// Generate a map from JSON schema to paths to JSON instances, ordered by schema and path.
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>>;
 }
@@ -1,39 +1,32 @@
 //! Suffix auto detection: Validate `<filename>.json` with `<filename>.schema.json` under working directory.
 
-use super::{Include, Schema};
+use super::{FileIndex, Include, Schema};
 use crate::regularize;
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::Arc,
 };
 
 /// A smart including feature of scheval, capable of finding `<filename>.json` with `<filename>.schema.json` under base directory.
 pub struct Suffix {
-    /// Canonicalized path to the base directory.
-    base: PathBuf,
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
 }
 
 impl Include for Suffix {
-    fn with_base(base: &str) -> Self {
-        let base = Path::new(base)
-            .canonicalize()
-            .expect("Failed to canonicalize base directory");
-        Self { base }
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self { index }
     }
-    fn get_associations(&self) -> HashMap<Schema, HashSet<PathBuf>> {
-        let base = &self.base;
-        let Ok(entries) = fs::read_dir(&base) else {
-            eprintln!("Failed to list base directory");
-            return HashMap::new();
-        };
-        let mut associations = HashMap::new();
-        for entry in entries {
-            let Ok(entry) = entry else {
-                eprintln!("Failed to read entry: {}", entry.unwrap_err());
-                continue;
-            };
-            let path = entry.path();
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        let base = self.index.base();
+        let mut associations = BTreeMap::new();
+        // Only files directly under the base directory
+        for path in self
+            .index
+            .files()
+            .filter(|path| path.components().count() == 1)
+        {
             let Some(file_name) = path.file_name() else {
                 eprintln!("Failed to get file name");
                 continue;
@@ -43,8 +36,8 @@ impl Include for Suffix {
                 continue;
             };
             if file_name.ends_with(".json") {
-                let schema_path = path.with_extension("schema.json");
-                if schema_path.exists() {
+                let schema_path = base.join(path).with_extension("schema.json");
+                if self.index.exists(&schema_path) {
                     let Ok(schema_path) = schema_path.canonicalize() else {
                         eprintln!(
                             "Failed to canonicalize schema path `{}`",
@@ -52,20 +45,12 @@ impl Include for Suffix {
                         );
                         continue;
                     };
-                    let schema_path = regularize(&base, &schema_path);
+                    let schema_path = regularize(base, &schema_path);
                     let schema = Schema::Local(schema_path);
-                    let Ok(instance) = path.canonicalize() else {
-                        eprintln!(
-                            "Failed to canonicalize instance path `{}`",
-                            path.to_string_lossy()
-                        );
-                        continue;
-                    };
-                    let instance = regularize(&base, &instance);
                     associations
                         .entry(schema)
-                        .or_insert_with(HashSet::new)
-                        .insert(instance);
+                        .or_insert_with(BTreeSet::new)
+                        .insert(path.to_path_buf());
                 }
             }
         }
@@ -76,16 +61,22 @@ impl Include for Suffix {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests_util::{hashset_of_pathbuf, TEST_DIR};
+    use crate::tests_util::{btreeset_of_pathbuf, TEST_DIR};
 
     #[test]
     fn test_suffix() {
         let inc = Suffix::with_base(TEST_DIR);
         let associations = inc.get_associations();
-        let expected: HashMap<Schema, HashSet<PathBuf>> = [(
-            Schema::Local(PathBuf::from("receipts.schema.json")),
-            hashset_of_pathbuf(&["receipts.json"]),
-        )]
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [
+            (
+                Schema::Local(PathBuf::from("receipts.schema.json")),
+                btreeset_of_pathbuf(&["receipts.json"]),
+            ),
+            (
+                Schema::Local(PathBuf::from("legacy.schema.json")),
+                btreeset_of_pathbuf(&["legacy.json"]),
+            ),
+        ]
         .into();
         assert_eq!(associations, expected);
     }
@@ -1,31 +1,37 @@
 //! VSCode auto detection: Respect `json.schemas` field at `.vscode/settings.json` if present
 // https://code.visualstudio.com/docs/languages/json#_json-schemas-and-settings
 
-use super::{Include, Schema};
-use crate::regularize;
-use globwalk::GlobWalkerBuilder;
+use super::{FileIndex, Include, Schema};
+use crate::{
+    regularize,
+    retrieve::{file_uri, set_default_id},
+};
+use data_url::DataUrl;
 use jsonc_parser::parse_to_serde_value;
+use percent_encoding::percent_decode_str;
 use serde_json::{Map, Value};
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
+    collections::{BTreeMap, BTreeSet},
+    env,
     path::{Path, PathBuf},
+    sync::Arc,
 };
+use url::Url;
 
 /// A smart including feature of scheval, capable of respecting `json.schemas` field at `.vscode/settings.json` if present.
 pub struct Vscode {
-    /// Canonicalized path to the base directory.
-    base: PathBuf,
+    /// Index of the base directory.
+    index: Arc<FileIndex>,
 }
 
-/// Read `json.schemas` field from `.vscode/settings.json` at `base`
-fn read_schema_associations_from_settings(base: &Path) -> Option<Vec<Value>> {
-    let settings_json = base.join(".vscode/settings.json");
-    if !settings_json.exists() {
+/// Read `json.schemas` field from `.vscode/settings.json` at the base directory of `index`
+fn read_schema_associations_from_settings(index: &FileIndex) -> Option<Vec<Value>> {
+    let settings_json = index.base().join(".vscode/settings.json");
+    if !index.exists(&settings_json) {
         eprintln!("No .vscode/settings.json found");
         return None;
     }
-    let Ok(settings_text) = fs::read_to_string(settings_json) else {
+    let Ok(settings_text) = index.read_to_string(&settings_json) else {
         eprintln!("Failed to read .vscode/settings.json");
         return None;
     };
@@ -44,8 +50,12 @@ fn read_schema_associations_from_settings(base: &Path) -> Option<Vec<Value>> {
     Some(association_definitions.to_vec())
 }
 
-/// Get schema from an association definition relative to `base`, **consuming** the definition
-fn get_schema(mut association_definition: Map<String, Value>, base: &Path) -> Option<Schema> {
+/// Get schema from an association definition relative to `base`, **consuming** the definition. Relative `$ref`s in inline schemas are resolved against `origin`, the file containing the definition
+fn get_schema(
+    mut association_definition: Map<String, Value>,
+    base: &Path,
+    origin: &Path,
+) -> Option<Schema> {
     // Unwrap the `url` or `schema` field (schema path or inline schema)
     let Some(schema_path) = association_definition.get("url") else {
         // If `url` field is not found, try `schema` field
@@ -58,23 +68,38 @@ fn get_schema(mut association_definition: Map<String, Value>, base: &Path) -> Op
             eprintln!("`schema` field is not an object");
             return None;
         };
+        let mut schema = schema;
+        set_default_id(&mut schema, &file_uri(origin));
         return Some(Schema::Inline(schema));
     };
     let Value::String(schema_path) = schema_path else {
         eprintln!("`url` field is not a string");
         return None;
     };
-    if schema_path.starts_with("http://") || schema_path.starts_with("https://") {
-        eprintln!("Remote schema is not supported");
-        return None;
+    get_schema_from_url(schema_path, base, base)
+}
+
+/// Get schema from a `url`, resolving relative paths against `dir`, or `base` if it starts with `/`. Supports `http(s):`, `file:` and `data:` URIs besides plain paths
+pub(crate) fn get_schema_from_url(url: &str, dir: &Path, base: &Path) -> Option<Schema> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Some(Schema::Remote(url.to_string()));
     }
-    let mut schema_path = schema_path.to_string();
-    // Resolve schema paths
-    if schema_path.starts_with('/') {
-        // Relative to workspace root
-        schema_path.remove(0); // Remove leading `/`
+    if url.starts_with("data:") {
+        return get_schema_from_data_uri(url);
     }
-    let schema_path = base.join(schema_path);
+    // Resolve schema paths
+    let schema_path = if let Some(path) = url.strip_prefix("file:") {
+        get_path_from_file_uri(url, path, dir)?
+    } else if has_scheme(url) {
+        eprintln!("Unsupported scheme in schema URL `{url}`");
+        return None;
+    } else {
+        match url.strip_prefix('/') {
+            // Relative to workspace root
+            Some(path) => base.join(path),
+            None => dir.join(url),
+        }
+    };
     let Ok(schema_path) = schema_path.canonicalize() else {
         eprintln!(
             "Failed to canonicalize schema path `{}`",
@@ -82,113 +107,230 @@ fn get_schema(mut association_definition: Map<String, Value>, base: &Path) -> Op
         );
         return None;
     };
-    let schema_path = regularize(&base, &schema_path);
+    let schema_path = regularize(base, &schema_path);
     Some(Schema::Local(schema_path))
 }
 
-impl Include for Vscode {
-    fn with_base(base: &str) -> Self {
-        let base = Path::new(base)
-            .canonicalize()
-            .expect("Failed to canonicalize base directory");
-        Self { base }
-    }
-    fn get_associations(&self) -> HashMap<Schema, HashSet<PathBuf>> {
-        let base = &self.base;
-        let Some(association_definitions) = read_schema_associations_from_settings(base) else {
-            return HashMap::new();
+/// Check whether `url` starts with a URI scheme. Single letters are considered Windows drive letters instead
+fn has_scheme(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once(':') else {
+        return false;
+    };
+    scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Get the path from a `file:` URI, where `path` is the part after the scheme. Absolute URIs (`file:///abs/path.json`) are converted to filesystem paths, while others (`file:./x.json`) are resolved against `dir`
+fn get_path_from_file_uri(url: &str, path: &str, dir: &Path) -> Option<PathBuf> {
+    if path.starts_with("//") {
+        let Some(path) = Url::parse(url).ok().and_then(|url| url.to_file_path().ok()) else {
+            eprintln!("Invalid file URI `{url}`");
+            return None;
         };
-        let mut associations = HashMap::new();
-        for association_definition in association_definitions {
-            // Unwrap the association object
-            let Value::Object(association_definition) = association_definition else {
-                eprintln!("A non-object element found under `json.schemas`");
-                continue;
-            };
+        return Some(path);
+    }
+    let Ok(path) = percent_decode_str(path).decode_utf8() else {
+        eprintln!("Invalid file URI `{url}`");
+        return None;
+    };
+    Some(dir.join(path.as_ref()))
+}
 
-            // Unwrap the `fileMatch` field (array of glob patterns)
-            let Some(file_match) = association_definition.get("fileMatch") else {
-                eprintln!("`fileMatch` field not found in schema");
-                continue;
-            };
-            let Value::Array(file_match) = file_match else {
-                eprintln!("`fileMatch` field is not an array");
-                continue;
+/// Get an inline schema from a `data:` URI, either base64 or percent encoded
+fn get_schema_from_data_uri(url: &str) -> Option<Schema> {
+    let Ok(data_url) = DataUrl::process(url) else {
+        eprintln!("Invalid data URI `{url}`");
+        return None;
+    };
+    let Ok((body, _)) = data_url.decode_to_vec() else {
+        eprintln!("Failed to decode data URI `{url}`");
+        return None;
+    };
+    match serde_json::from_slice(&body) {
+        Ok(schema @ Value::Object(_)) => Some(Schema::Inline(schema)),
+        Ok(_) => {
+            eprintln!("Schema in data URI `{url}` is not an object");
+            None
+        }
+        Err(error) => {
+            eprintln!("Failed to parse schema in data URI `{url}`: {error}");
+            None
+        }
+    }
+}
+
+/// Get glob patterns from a `fileMatch` array, skipping unsupported ones
+pub(crate) fn get_patterns(file_match: &[Value]) -> Vec<String> {
+    file_match
+        .iter()
+        .filter_map(|pattern| {
+            let Value::String(pattern) = pattern else {
+                eprintln!("`fileMatch` field contains non-string element");
+                return None;
             };
-            let patterns = file_match
-                .iter()
-                .filter_map(|pattern| {
-                    let Value::String(pattern) = pattern else {
-                        eprintln!("`fileMatch` field contains non-string element");
-                        return None;
-                    };
-                    let mut pattern = pattern.to_string();
-                    // Quick fix and warning for https://github.com/Gilnaa/globwalk/issues/28
-                    if pattern.starts_with("./") {
-                        pattern = pattern[2..].to_string();
-                    } else if pattern.starts_with("../") {
-                        eprintln!("`fileMatch` patterns starting with `../` are not supported");
-                        return None;
-                    }
-                    Some(pattern)
-                })
-                .collect::<Vec<_>>();
+            let mut pattern = pattern.to_string();
+            // Quick fix and warning for https://github.com/Gilnaa/globwalk/issues/28
+            if pattern.starts_with("./") {
+                pattern = pattern[2..].to_string();
+            } else if pattern.starts_with("../") {
+                eprintln!("`fileMatch` patterns starting with `../` are not supported");
+                return None;
+            }
+            Some(pattern)
+        })
+        .collect()
+}
+
+/// Generate associations from definitions in the format of `json.schemas` found in `origin`, relative to the base directory of `index`
+pub(crate) fn get_associations_from_definitions(
+    association_definitions: Vec<Value>,
+    index: &FileIndex,
+    origin: &Path,
+) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+    let base = index.base();
+    let mut associations = BTreeMap::new();
+    for association_definition in association_definitions {
+        // Unwrap the association object
+        let Value::Object(association_definition) = association_definition else {
+            eprintln!("A non-object association definition found");
+            continue;
+        };
+
+        // Unwrap the `fileMatch` field (array of glob patterns)
+        let Some(file_match) = association_definition.get("fileMatch") else {
+            eprintln!("`fileMatch` field not found in schema");
+            continue;
+        };
+        let Value::Array(file_match) = file_match else {
+            eprintln!("`fileMatch` field is not an array");
+            continue;
+        };
+        let patterns = get_patterns(file_match);
+
+        // Unwrap the `url` or `schema` field (schema path or inline schema)
+        let Some(schema) = get_schema(association_definition, base, origin) else {
+            eprintln!("Failed to get schema from association definition");
+            continue;
+        };
+
+        // Collect instances
+        let instances = index.matching(&patterns);
+
+        // Update associations
+        associations
+            .entry(schema)
+            .or_insert_with(BTreeSet::new)
+            .extend(instances);
+    }
+    associations
+}
 
-            // Unwrap the `url` or `schema` field (schema path or inline schema)
-            let Some(schema) = get_schema(association_definition, &base) else {
-                eprintln!("Failed to get schema from association definition");
+/// Substitute VSCode variables in the `url` and `fileMatch` fields of an association definition
+fn substitute_definition(mut association_definition: Value, base: &Path) -> Value {
+    if let Some(Value::String(url)) = association_definition.get_mut("url") {
+        let substituted = substitute_variables(url, base);
+        // Absolute paths (e.g. `${workspaceFolder}/schema.json`) are filesystem paths, instead of relative to workspace root
+        *url = if substituted != *url && Path::new(&substituted).is_absolute() {
+            file_uri(Path::new(&substituted))
+        } else {
+            substituted
+        };
+    }
+    if let Some(Value::Array(file_match)) = association_definition.get_mut("fileMatch") {
+        for pattern in file_match {
+            let Value::String(pattern) = pattern else {
                 continue;
             };
+            // Patterns starting with `${workspaceFolder}` are relative to workspace root
+            let (negation, rest) = match pattern.strip_prefix('!') {
+                Some(rest) => ("!", rest),
+                None => ("", pattern.as_str()),
+            };
+            let rest = rest.strip_prefix("${workspaceFolder}").unwrap_or(rest);
+            *pattern = format!("{negation}{}", substitute_variables(rest, base));
+        }
+    }
+    association_definition
+}
 
-            // Create a GlobWalker for given patterns
-            let builder = GlobWalkerBuilder::from_patterns(base, &patterns);
-
-            // Collect instances
-            let instances = builder
-                .build()
-                .expect("Failed to build GlobWalker")
-                .filter_map(|item| {
-                    let Ok(item) = item else {
-                        eprintln!("Failed to read item: {}", item.unwrap_err());
-                        return None;
-                    };
-                    let Ok(path) = item.path().canonicalize() else {
-                        eprintln!(
-                            "Failed to canonicalize instance path `{}`",
-                            item.path().to_string_lossy()
-                        );
-                        return None;
-                    };
-                    Some(regularize(&base, &path))
-                })
-                .collect::<HashSet<_>>();
-
-            // Update associations
-            associations
-                .entry(schema)
-                .or_insert_with(HashSet::new)
-                .extend(instances);
+/// Substitute `${workspaceFolder}`, `${workspaceFolderBasename}`, `${userHome}` and `${env:NAME}` in `text`, leaving unsupported variables as is
+// https://code.visualstudio.com/docs/reference/variables-reference
+fn substitute_variables(text: &str, base: &Path) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        let variable = &rest[start + 2..start + length];
+        result.push_str(&rest[..start]);
+        match variable {
+            "workspaceFolder" => result.push_str(&base.to_string_lossy()),
+            "workspaceFolderBasename" => {
+                result.push_str(&base.file_name().unwrap_or_default().to_string_lossy())
+            }
+            "userHome" => {
+                let home = env::var("HOME").or_else(|_| env::var("USERPROFILE"));
+                result.push_str(&home.unwrap_or_default());
+            }
+            _ => match variable.strip_prefix("env:") {
+                Some(name) => result.push_str(&env::var(name).unwrap_or_default()),
+                None => {
+                    eprintln!("Unsupported variable `${{{variable}}}` in `{text}`");
+                    result.push_str(&rest[start..=start + length]);
+                }
+            },
         }
-        return associations;
+        rest = &rest[start + length + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+impl Include for Vscode {
+    fn with_index(index: Arc<FileIndex>) -> Self {
+        Self { index }
+    }
+    fn get_associations(&self) -> BTreeMap<Schema, BTreeSet<PathBuf>> {
+        let base = self.index.base();
+        let Some(association_definitions) = read_schema_associations_from_settings(&self.index)
+        else {
+            return BTreeMap::new();
+        };
+        let settings_json = base.join(".vscode/settings.json");
+        let association_definitions = association_definitions
+            .into_iter()
+            .map(|definition| substitute_definition(definition, base))
+            .collect();
+        get_associations_from_definitions(association_definitions, &self.index, &settings_json)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests_util::{hashset_of_pathbuf, TEST_DIR};
+    use crate::tests_util::{btreeset_of_pathbuf, temp_dir, TEST_DIR};
 
     #[test]
     fn test_vscode() {
         let inc = Vscode::with_base(TEST_DIR);
         let associations = inc.get_associations();
-        let expected: HashMap<Schema, HashSet<PathBuf>> = [
+        let settings_json = Path::new(TEST_DIR)
+            .join(".vscode/settings.json")
+            .canonicalize()
+            .unwrap();
+        let settings_uri = file_uri(&settings_json);
+        let expected: BTreeMap<Schema, BTreeSet<PathBuf>> = [
             (
                 Schema::Local(PathBuf::from("foo_schema.json")),
-                hashset_of_pathbuf(&["nested1/nested2/abc.foo.json"]),
+                btreeset_of_pathbuf(&["nested1/nested2/abc.foo.json"]),
             ),
             (
                 Schema::Inline(serde_json::json!({
+                    "$id": settings_uri,
                     "type": "object",
                     "properties": {
                         "name": {
@@ -197,14 +339,102 @@ mod tests {
                         }
                     }
                 })),
-                hashset_of_pathbuf(&[".myconfig"]),
+                btreeset_of_pathbuf(&[".myconfig"]),
             ),
             (
                 Schema::Local(PathBuf::from("receipts.schema.json")),
-                hashset_of_pathbuf(&["receipts/1.json", "receipts/2.json"]),
+                btreeset_of_pathbuf(&["receipts/1.json", "receipts/2.json"]),
+            ),
+            (
+                Schema::Local(PathBuf::from("refs/person.schema.json")),
+                btreeset_of_pathbuf(&["refs/alice.person.json", "refs/carol.friend.json"]),
+            ),
+            (
+                Schema::Inline(serde_json::json!({
+                    "$id": settings_uri,
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "$ref": "../refs/common.json#/$defs/name"
+                        }
+                    }
+                })),
+                btreeset_of_pathbuf(&["refs/bob.pet.json"]),
             ),
         ]
         .into();
         assert_eq!(associations, expected);
     }
+
+    #[test]
+    fn test_schema_url() {
+        let base = Path::new(TEST_DIR).canonicalize().unwrap();
+        let dir = base.join("refs");
+        let local = Some(Schema::Local(PathBuf::from("refs/common.json")));
+        let absolute = file_uri(&dir.join("common.json"));
+        assert_eq!(get_schema_from_url(&absolute, &dir, &base), local);
+        assert_eq!(
+            get_schema_from_url("file:./common.json", &dir, &base),
+            local
+        );
+        assert_eq!(get_schema_from_url("file:common.json", &dir, &base), local);
+        let inline = Some(Schema::Inline(serde_json::json!({ "type": "object" })));
+        assert_eq!(
+            get_schema_from_url(
+                "data:application/json,%7B%22type%22:%22object%22%7D",
+                &dir,
+                &base
+            ),
+            inline
+        );
+        assert_eq!(
+            get_schema_from_url(
+                "data:application/json;base64,eyJ0eXBlIjoib2JqZWN0In0=",
+                &dir,
+                &base
+            ),
+            inline
+        );
+        assert_eq!(
+            get_schema_from_url("vscode://schemas/settings", &dir, &base),
+            None
+        );
+    }
+
+    #[test]
+    fn test_substitute_variables() {
+        let dir = temp_dir();
+        let base = &dir.path().canonicalize().unwrap();
+        let basename = base.file_name().unwrap().to_string_lossy();
+        env::set_var("SCHEVAL_TEST_VAR", "value");
+        assert_eq!(
+            substitute_variables("${workspaceFolder}/schemas/x.json", base),
+            format!("{}/schemas/x.json", base.display())
+        );
+        assert_eq!(
+            substitute_variables(
+                "${workspaceFolderBasename}-${env:SCHEVAL_TEST_VAR}.json",
+                base
+            ),
+            format!("{basename}-value.json")
+        );
+        assert_eq!(
+            substitute_variables("${unknown}/${env:SCHEVAL_TEST_MISSING}x", base),
+            "${unknown}/x"
+        );
+        let definition = substitute_definition(
+            serde_json::json!({
+                "fileMatch": ["${workspaceFolder}/a/*.json", "!${workspaceFolder}/a/b.json"],
+                "url": "${workspaceFolder}/schema.json"
+            }),
+            base,
+        );
+        assert_eq!(
+            definition,
+            serde_json::json!({
+                "fileMatch": ["/a/*.json", "!/a/b.json"],
+                "url": file_uri(&base.join("schema.json"))
+            })
+        );
+    }
 }
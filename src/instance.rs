@@ -0,0 +1,321 @@
+//! Instance reading: Read JSON instances with size and nesting depth limits, memory-mapping huge files, and parse them as JSON or JSONC.
+
+use crate::git::Staged;
+use jsonc_parser::parse_to_serde_value;
+use memmap2::Mmap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{error::Error, fs::File, io::Read, ops::Deref, path::Path};
+
+/// Files at least this large are memory-mapped instead of read into memory.
+const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+/// Default maximum nesting depth of instances, the same as the recursion limit of `serde_json`.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 128;
+/// Upper bound of the maximum nesting depth. Parsing, validating and dropping instances recurse per level, which fits in [`STACK_SIZE`] up to this depth.
+pub(crate) const MAX_DEPTH: usize = 10_000;
+/// Stack size of threads parsing and validating instances.
+pub(crate) const STACK_SIZE: usize = 128 * 1024 * 1024;
+
+/// Limits guarding against pathological instances.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    /// Maximum size of instances in bytes, if any.
+    pub(crate) max_size: Option<u64>,
+    /// Maximum nesting depth of arrays and objects in instances.
+    pub(crate) max_depth: usize,
+    /// Whether huge instances may be memory-mapped, which assumes they aren't modified while being read.
+    pub(crate) mmap: bool,
+}
+
+impl Limits {
+    /// Create limits with given maximum size and depth, clamping the depth to [`MAX_DEPTH`].
+    pub(crate) fn new(max_size: Option<u64>, max_depth: Option<usize>) -> Self {
+        Self {
+            max_size,
+            max_depth: max_depth.unwrap_or(DEFAULT_MAX_DEPTH).min(MAX_DEPTH),
+            mmap: true,
+        }
+    }
+
+    /// Always read instances into memory, e.g. when they might be edited meanwhile. Truncating a memory-mapped file would crash the process.
+    pub(crate) fn without_mmap(mut self) -> Self {
+        self.mmap = false;
+        self
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+/// Content of an instance within limits, either read into memory or memory-mapped.
+pub(crate) enum Content {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Content {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Read(content) => content,
+            Self::Mapped(content) => content,
+        }
+    }
+}
+
+/// Read the instance at `path`, from the git index if `staged` is given and the path is inside the repository, failing if it's larger or nested deeper than the limits.
+pub(crate) fn read_instance(
+    path: &Path,
+    limits: &Limits,
+    staged: Option<&Staged>,
+) -> Result<Content, Box<dyn Error>> {
+    if let Some(content) = staged.and_then(|staged| staged.read(path)) {
+        let content = content?;
+        check_size(path, content.len() as u64, limits)?;
+        return check_depth(path, Content::Read(content), limits);
+    }
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    check_size(path, size, limits)?;
+    let content = if limits.mmap && size >= MMAP_THRESHOLD {
+        // SAFETY: The mapping is read-only, and instances are not expected to be modified during validation, unless memory-mapping is disabled
+        Content::Mapped(unsafe { Mmap::map(&file)? })
+    } else {
+        let mut content = Vec::with_capacity(size as usize);
+        file.read_to_end(&mut content)?;
+        Content::Read(content)
+    };
+    check_depth(path, content, limits)
+}
+
+/// Fail if the instance at `path` with `size` is larger than the limit.
+fn check_size(path: &Path, size: u64, limits: &Limits) -> Result<(), Box<dyn Error>> {
+    match limits.max_size.filter(|&max_size| size > max_size) {
+        Some(max_size) => Err(format!(
+            "Instance `{}` is {size} bytes, exceeding the limit of {max_size} bytes",
+            path.to_string_lossy()
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Pass `content` of the instance at `path` through, failing if it's nested deeper than the limit.
+fn check_depth(path: &Path, content: Content, limits: &Limits) -> Result<Content, Box<dyn Error>> {
+    if nesting_depth_exceeds(&content, limits.max_depth, is_jsonc(path)) {
+        return Err(format!(
+            "Instance `{}` is nested deeper than the limit of {}",
+            path.to_string_lossy(),
+            limits.max_depth
+        )
+        .into());
+    }
+    Ok(content)
+}
+
+/// Parse the `content` of the instance at `path`, allowing comments and trailing commas if it's [JSONC](is_jsonc).
+pub(crate) fn parse_instance(path: &Path, content: &Content) -> Result<Value, Box<dyn Error>> {
+    let parsed = if is_jsonc(path) {
+        parse_jsonc(content)
+    } else {
+        parse_json(content)
+    };
+    parsed.map_err(|error| format!("Failed to parse `{}`: {error}", path.to_string_lossy()).into())
+}
+
+/// Parse strict JSON `content`.
+fn parse_json(content: &[u8]) -> Result<Value, Box<dyn Error>> {
+    let mut deserializer = serde_json::Deserializer::from_slice(content);
+    // Nesting depth is already checked by `read_instance`
+    deserializer.disable_recursion_limit();
+    let value = Value::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Parse JSON `content` with comments and trailing commas.
+fn parse_jsonc(content: &[u8]) -> Result<Value, Box<dyn Error>> {
+    let text = std::str::from_utf8(content)?;
+    parse_to_serde_value(text, &Default::default())?.ok_or_else(|| "Empty content".into())
+}
+
+/// Check whether the instance at `path` is JSON with comments by convention: `*.jsonc` files, `tsconfig.json`, `jsconfig.json` (and their `*.*.json` and `*-*.json` variants), `devcontainer.json` and files under `.vscode`.
+pub(crate) fn is_jsonc(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    if name.ends_with(".jsonc") || name == "devcontainer.json" || name == ".devcontainer.json" {
+        return true;
+    }
+    if ["tsconfig", "jsconfig"].iter().any(|prefix| {
+        name.strip_prefix(prefix).is_some_and(|rest| {
+            rest == ".json" || rest.starts_with(['.', '-']) && rest.ends_with(".json")
+        })
+    }) {
+        return true;
+    }
+    path.parent()
+        .and_then(Path::file_name)
+        .is_some_and(|parent| parent == ".vscode")
+}
+
+/// Check whether arrays and objects in JSON `content` are nested deeper than `max_depth`, without parsing it. If `jsonc`, brackets and quotes in `//` and `/* */` comments are skipped.
+fn nesting_depth_exceeds(content: &[u8], max_depth: usize, jsonc: bool) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut bytes = content.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            b'/' if jsonc && bytes.peek() == Some(&b'/') => {
+                bytes.find(|&byte| byte == b'\n');
+            }
+            b'/' if jsonc && bytes.peek() == Some(&b'*') => {
+                bytes.next();
+                let mut star = false;
+                for byte in bytes.by_ref() {
+                    if star && byte == b'/' {
+                        break;
+                    }
+                    star = byte == b'*';
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nesting_depth() {
+        assert!(!nesting_depth_exceeds(
+            br#"{"a": [[1], {"b": "[[[["}]}"#,
+            3,
+            false
+        ));
+        assert!(nesting_depth_exceeds(
+            br#"{"a": [[1], {"b": [[]]}]}"#,
+            3,
+            false
+        ));
+        assert!(!nesting_depth_exceeds(br#"["\"[[[[", "\\", []]"#, 2, false));
+        assert!(!nesting_depth_exceeds(
+            "[".repeat(128).as_bytes(),
+            DEFAULT_MAX_DEPTH,
+            false
+        ));
+        assert!(nesting_depth_exceeds(
+            "[".repeat(129).as_bytes(),
+            DEFAULT_MAX_DEPTH,
+            false
+        ));
+        // Quotes and brackets in comments don't count
+        let deep = format!("// \"\n{}", "[".repeat(129));
+        assert!(nesting_depth_exceeds(
+            deep.as_bytes(),
+            DEFAULT_MAX_DEPTH,
+            true
+        ));
+        let deep = format!("/* \" [ */ {}", "[".repeat(129));
+        assert!(nesting_depth_exceeds(
+            deep.as_bytes(),
+            DEFAULT_MAX_DEPTH,
+            true
+        ));
+        let shallow = b"{\n  // say \"hi\n  \"a\": \"[[[[\" /* [[[[ */\n}";
+        assert!(!nesting_depth_exceeds(shallow, 3, true));
+        assert!(nesting_depth_exceeds(br#"["http://", [[[]]]]"#, 3, true));
+    }
+
+    #[test]
+    fn test_mmap() {
+        let dir = crate::tests_util::temp_dir();
+        let path = dir.path().join("huge.json");
+        let mut content = vec![b' '; MMAP_THRESHOLD as usize];
+        content.extend(b"{}");
+        std::fs::write(&path, content).unwrap();
+        let limits = Limits::default();
+        let mapped = read_instance(&path, &limits, None).unwrap();
+        assert!(matches!(mapped, Content::Mapped(_)));
+        let read = read_instance(&path, &limits.without_mmap(), None).unwrap();
+        assert!(matches!(read, Content::Read(_)));
+        assert_eq!(parse_instance(&path, &read).unwrap(), serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        assert_eq!(Limits::new(None, Some(usize::MAX)).max_depth, MAX_DEPTH);
+        let content = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH)).into_bytes();
+        let schema = serde_json::json!({
+            "$defs": { "nested": { "items": { "$ref": "#/$defs/nested" }, "not": { "type": "string" } } },
+            "$ref": "#/$defs/nested"
+        });
+        // Would overflow the default stack of 2 MiB
+        std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let limits = Limits::new(None, Some(MAX_DEPTH));
+                let content =
+                    check_depth(Path::new("deep.json"), Content::Read(content), &limits).unwrap();
+                for path in ["deep.json", "deep.jsonc"] {
+                    let instance = parse_instance(Path::new(path), &content).unwrap();
+                    let validator = jsonschema::validator_for(&schema).unwrap();
+                    assert_eq!(validator.iter_errors(&instance).count(), 0);
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_parse_instance() {
+        let jsonc = Content::Read(b"{\n  // Comment\n  \"a\": [1, 2,],\n}".to_vec());
+        for path in [
+            "tsconfig.json",
+            "packages/tsconfig.build.json",
+            "tsconfig-base.json",
+            "jsconfig.json",
+            ".devcontainer/devcontainer.json",
+            ".vscode/extensions.json",
+            "settings.jsonc",
+        ] {
+            assert_eq!(
+                parse_instance(Path::new(path), &jsonc).unwrap(),
+                serde_json::json!({ "a": [1, 2] }),
+                "{path}"
+            );
+        }
+        for path in ["package.json", "tsconfig-json", "vscode/extensions.json"] {
+            let error = parse_instance(Path::new(path), &jsonc).unwrap_err();
+            assert!(error
+                .to_string()
+                .starts_with(&format!("Failed to parse `{path}`")));
+        }
+    }
+}
@@ -20,110 +20,507 @@
 //! The main entry point of the library is the [`run`] function, which takes a [`Config`] and a base directory as input, and returns a `Result`.
 //!
 //! - The [`Config`] struct offers a simple way to configure what smart including features to use, and can be constructed either manually or automatically from command line arguments using the [`get_config`] function.
-//! - The base directory is a string slice representing the base path, or working directory, from which we start to search for JSON instances and schemas and resolve relative paths.
+//! - The base directory is a string slice representing the base path, or working directory, from which we start to search for JSON instances and schemas and resolve relative paths. The [`get_bases`] function resolves base directories given by `--base` or found by `--find-base` relative to the working directory, so that paths in reports are relative to it too.
 //! - The return value is a `Result`, where:
 //!     - `Ok(true)` indicates that all instances are valid.
 //!     - `Ok(false)` indicates that at least one instance is invalid, or a invalid schema is encountered.
-//!     - `Err(error)` indicates that an error occurred during the validation process. It could be an I/O error, a JSON parsing error etc.
+//!     - `Err(error)` indicates that an error occurred during the validation process. It could be an I/O error, a JSON parsing error of a schema etc. Instances that can't be read or parsed are reported and count as invalid instead.
+//!
+//! Reports are printed in a deterministic order: Schemas are ordered as [`Schema`]s (local paths, then remote URLs, inline schemas and built-in schemas), and instances of each schema are ordered by path. Associations returned by [`include::Include`] implementations are ordered the same way.
 //!
 //! Refer to the binary crate for a complete example of using the `scheval` library crate.
 
+mod cache;
+mod git;
 pub mod include;
+mod instance;
+mod lock;
+mod meta;
+mod retrieve;
+mod vendor;
+mod watch;
+use cache::{result_key, schema_digest, Cache, CACHE_DIR};
 use clap::{
     builder::styling::{AnsiColor, Color, Style, Styles},
-    Parser,
+    error::ErrorKind,
+    CommandFactory, Parser, Subcommand,
 };
-use include::Include;
+use git::Staged;
+use include::{declares_json_validation, FileIndex, Include};
+use instance::{is_jsonc, parse_instance, read_instance, Limits, MAX_DEPTH, STACK_SIZE};
+use jsonschema::Draft;
+use lock::{lock_key, Lockfile, LOCKFILE};
+use meta::{MetaChecker, Problem, Severity};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use retrieve::{has_relative_refs, set_draft4_id, SchemaRetriever};
 use serde_json::Value;
 use std::{
-    collections::{HashMap, HashSet},
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     error::Error,
-    fmt::Display,
-    fs::File,
-    io::BufReader,
-    path::{Path, PathBuf},
+    ffi::OsString,
+    fmt::{Display, Write},
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
+use url::Url;
+use vendor::{local_refs, sha256_hex, Vendored, DEFAULT_VENDOR_DIR};
 
 // Arguments & Configuration
 
 /// Command-line arguments.
 #[derive(Parser, Debug)]
-#[command(version, about = format!("A fast and {ITALIC}*smart*{ITALIC:#} command-line tool for JSON Schema validation, powered by the {UNDERLINE}`jsonschema`{UNDERLINE:#} crate."), long_about = None, styles = CLAP_STYLE)]
+#[command(version, subcommand_precedence_over_arg = true, about = format!("A fast and {ITALIC}*smart*{ITALIC:#} command-line tool for JSON Schema validation, powered by the {UNDERLINE}`jsonschema`{UNDERLINE:#} crate."), long_about = None, styles = CLAP_STYLE)]
 struct Args {
-    /// What smart including features to use. Available: `vscode`, `suffix`. Default to all
+    /// What smart including features to use. Available: `vscode`, `extension`, `jetbrains`, `manifest`, `suffix`, `catalog`, `builtin`. Default to all
     ///
     /// - `vscode`: Respect `json.schemas` field at `.vscode/settings.json` if present
+    /// - `extension`: Respect `contributes.jsonValidation` field at `package.json` of VSCode extensions in the workspace
+    /// - `jetbrains`: Respect JSON Schema mappings at `.idea/jsonSchemas.xml` if present
+    /// - `manifest`: Respect `schemas` field at `[package.metadata.scheval]` / `[workspace.metadata.scheval]` in `Cargo.toml` and `[tool.scheval]` in `pyproject.toml` if present
     /// - `suffix`: Validate `<filename>.json` with `<filename>.schema.json` under working directory
-    #[arg(short, long, verbatim_doc_comment)]
+    /// - `catalog`: Associate well-known files with schemas listed in a SchemaStore-format catalog
+    /// - `builtin`: Validate well-known config files with bundled schemas (requires the `builtin-schemas` cargo feature)
+    #[arg(short, long, verbatim_doc_comment, global = true)]
     include: Vec<String>,
-    // /// What smart excluding features to use. Available: TBD
-    // #[arg(short, long)]
-    // exclude: Vec<String>,
+    /// Path to a SchemaStore-format `catalog.json` used by `catalog`. Default to the bundled snapshot if available
+    #[arg(long, value_name = "PATH", global = true)]
+    catalog: Option<PathBuf>,
+    /// Directory of schemas indexed by their `$id`, so that `$ref`s to these URLs are resolved to local files. Can be repeated
+    #[arg(long = "schema-dir", value_name = "DIR", global = true)]
+    schema_dirs: Vec<PathBuf>,
+    /// Rewrite remote schema URLs starting with `FROM` to start with `TO`, which is either a URL or a local path. Can be repeated
+    #[arg(long = "rewrite", value_name = "FROM=TO", value_parser = parse_rewrite, global = true)]
+    rewrites: Vec<(String, String)>,
+    /// Directory of vendored remote schemas, which are used instead of fetching if present. Default to `.scheval/vendor`
+    #[arg(long, value_name = "DIR", global = true)]
+    vendor_dir: Option<PathBuf>,
+    /// JSON Schema draft used for schemas without `$schema`. Available: `4`, `6`, `7`, `2019-09`, `2020-12`. Default to `2020-12`
+    #[arg(long, value_name = "DRAFT", value_parser = parse_draft, global = true)]
+    draft: Option<Draft>,
+    /// JSON Schema draft used for given schema (path relative to base directory, or URL) if it lacks `$schema`, overriding `--draft`. Can be repeated
+    #[arg(long = "schema-draft", value_name = "SCHEMA=DRAFT", value_parser = parse_schema_draft, global = true)]
+    schema_drafts: Vec<(String, Draft)>,
+    /// Path to a custom meta-schema that all schemas are validated against, in addition to the meta-schema of their draft
+    #[arg(long, value_name = "PATH", global = true)]
+    meta_schema: Option<PathBuf>,
+    /// Number of threads used for validation. Default to the number of CPUs
+    #[arg(short, long, value_name = "N", global = true)]
+    jobs: Option<usize>,
+    /// Fail if any schema has changed since `scheval.lock` was last updated by `scheval lock`
+    #[arg(long, global = true)]
+    locked: bool,
+    /// Skip paths matching given gitignore-style pattern when looking for instances and schemas. Can be repeated
+    #[arg(long = "exclude", value_name = "PATTERN", global = true)]
+    excludes: Vec<String>,
+    /// Don't reuse or record validation results in `.scheval/cache`
+    #[arg(long, global = true)]
+    no_cache: bool,
+    /// Fail on instances larger than given size, in bytes or with a `K`, `M` or `G` suffix (powers of 1024). Default to no limit
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, global = true)]
+    max_file_size: Option<u64>,
+    /// Fail on instances with arrays and objects nested deeper than given depth, at most 10000. Default to 128
+    #[arg(long, value_name = "N", value_parser = parse_depth, global = true)]
+    max_depth: Option<usize>,
+    /// Only validate instances changed in git relative to given revision (staged, unstaged or untracked), and instances of schemas that changed, including via `$ref`s
+    #[arg(long, value_name = "REV", global = true)]
+    since: Option<String>,
+    /// Only validate instances changed in git since the last commit, and instances of schemas that changed. Shorthand for `--since HEAD`
+    #[arg(long, conflicts_with = "since", global = true)]
+    changed: bool,
+    /// Read instances and schemas from the git index instead of the working tree, so that exactly what will be committed is validated. With `--changed` or `--since`, only changes in the index are considered
+    #[arg(long, global = true)]
+    staged: bool,
+    /// Base directory to look for instances and schemas in, and resolve paths in settings against. Can be repeated for multiple roots. Default to the working directory
+    #[arg(long = "base", value_name = "DIR", global = true)]
+    bases: Vec<PathBuf>,
+    /// Use the nearest ancestor of the working directory containing `.vscode`, `.idea`, `.git` or a project manifest (`Cargo.toml`, `pyproject.toml`, `package.json`) as base directory
+    #[arg(long, conflicts_with = "bases", global = true)]
+    find_base: bool,
+    /// Read more files to validate from given path, or standard input if `-`. Paths are separated by newlines, or NUL characters if any
+    #[arg(long, value_name = "PATH")]
+    files_from: Option<PathBuf>,
+    /// Only validate given instances, and instances of given schemas (including via `$ref`s). Default to all discovered instances
+    #[arg(value_name = "FILE")]
+    files: Vec<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Subcommands. Validate instances if none is given.
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Download all remote schemas and remote `$ref`s into the vendor directory, and record their hashes in a lockfile
+    Vendor,
+    /// Record a content hash for every schema used into `scheval.lock`
+    Lock,
+    /// Validate all discovered schemas against their meta-schemas, and report unresolvable `$ref`s, unknown keywords and malformed regexes
+    Schemas,
+    /// Validate instances, then re-validate affected ones whenever files change
+    Watch,
+    /// Manage the validation cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+}
+
+/// Subcommands of `cache`.
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCommand {
+    /// Remove all cached validation results
+    Clean,
 }
 
 /// Configuration options. (Simple wrapper around `Args`)
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Config {
     pub vscode: bool,
+    pub extension: bool,
+    pub jetbrains: bool,
+    pub manifest: bool,
     pub suffix: bool,
+    pub catalog: bool,
+    pub builtin: bool,
+    /// Path to the catalog file used by `catalog`.
+    pub catalog_path: Option<PathBuf>,
+    /// Directories of schemas indexed by their `$id`.
+    pub schema_dirs: Vec<PathBuf>,
+    /// URL prefix rewrites for remote schemas, as `(from, to)` pairs.
+    pub rewrites: Vec<(String, String)>,
+    /// Directory of vendored remote schemas. Default to `.scheval/vendor` under the base directory.
+    pub vendor_dir: Option<PathBuf>,
+    /// JSON Schema draft used for schemas without `$schema`.
+    pub draft: Option<Draft>,
+    /// JSON Schema drafts used for specific schemas without `$schema`, as `(schema, draft)` pairs.
+    pub schema_drafts: Vec<(String, Draft)>,
+    /// Path to a custom meta-schema that all schemas are validated against.
+    pub meta_schema: Option<PathBuf>,
+    /// Number of threads used for validation. Default to the number of CPUs.
+    pub jobs: Option<usize>,
+    /// Whether to fail if any schema doesn't match `scheval.lock`.
+    pub locked: bool,
+    /// Gitignore-style patterns of paths to skip when looking for instances and schemas.
+    pub excludes: Vec<String>,
+    /// Whether to reuse and record validation results in `.scheval/cache` under the base directory, so that unchanged instances are skipped.
+    pub cache: bool,
+    /// Maximum size of instances in bytes. Default to no limit.
+    pub max_file_size: Option<u64>,
+    /// Maximum nesting depth of instances, clamped to 10000. Default to 128.
+    pub max_depth: Option<usize>,
+    /// Git revision to compare against, so that only affected instances are validated. Default to validating all instances.
+    pub since: Option<String>,
+    /// Whether to read instances and schemas from the git index instead of the working tree.
+    pub staged: bool,
+    /// Base directories, relative to the working directory. Default to the working directory.
+    pub bases: Vec<PathBuf>,
+    /// Whether to find the base directory by walking up from the working directory. Overrides `bases`.
+    pub find_base: bool,
+    /// Files to restrict validation to, relative to the working directory.
+    pub files: Vec<PathBuf>,
+    /// Path to a list of more files to restrict validation to, or `-` for standard input.
+    pub files_from: Option<PathBuf>,
+    /// Subcommand to run.
+    pub command: Option<Command>,
 }
 
 impl From<Args> for Config {
     fn from(args: Args) -> Self {
         let all = args.include.is_empty();
         let vscode = args.include.contains(&"vscode".to_string()) || all;
+        let extension = args.include.contains(&"extension".to_string()) || all;
+        let jetbrains = args.include.contains(&"jetbrains".to_string()) || all;
+        let manifest = args.include.contains(&"manifest".to_string()) || all;
         let suffix = args.include.contains(&"suffix".to_string()) || all;
-        Self { vscode, suffix }
+        let catalog = args.include.contains(&"catalog".to_string()) || all;
+        let builtin = args.include.contains(&"builtin".to_string())
+            || (all && cfg!(feature = "builtin-schemas"));
+        Self {
+            vscode,
+            extension,
+            jetbrains,
+            manifest,
+            suffix,
+            catalog,
+            builtin,
+            catalog_path: args.catalog,
+            schema_dirs: args.schema_dirs,
+            rewrites: args.rewrites,
+            vendor_dir: args.vendor_dir,
+            draft: args.draft,
+            schema_drafts: args.schema_drafts,
+            meta_schema: args.meta_schema,
+            jobs: args.jobs,
+            locked: args.locked,
+            excludes: args.excludes,
+            cache: !args.no_cache,
+            max_file_size: args.max_file_size,
+            max_depth: args.max_depth,
+            since: args
+                .since
+                .or_else(|| args.changed.then(|| "HEAD".to_string())),
+            staged: args.staged,
+            bases: args.bases,
+            find_base: args.find_base,
+            files: args.files,
+            files_from: args.files_from,
+            command: args.command,
+        }
     }
 }
 
+/// Parse a `FROM=TO` rewrite rule.
+fn parse_rewrite(rule: &str) -> Result<(String, String), String> {
+    rule.split_once('=')
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .ok_or_else(|| format!("Invalid rewrite rule `{rule}`, expected `FROM=TO`"))
+}
+
+/// Parse a JSON Schema draft.
+fn parse_draft(draft: &str) -> Result<Draft, String> {
+    match draft {
+        "4" => Ok(Draft::Draft4),
+        "6" => Ok(Draft::Draft6),
+        "7" => Ok(Draft::Draft7),
+        "2019-09" => Ok(Draft::Draft201909),
+        "2020-12" => Ok(Draft::Draft202012),
+        _ => Err(format!(
+            "Unknown draft `{draft}`, expected one of `4`, `6`, `7`, `2019-09`, `2020-12`"
+        )),
+    }
+}
+
+/// Parse a `SCHEMA=DRAFT` rule.
+fn parse_schema_draft(rule: &str) -> Result<(String, Draft), String> {
+    let Some((schema, draft)) = rule.rsplit_once('=') else {
+        return Err(format!("Invalid rule `{rule}`, expected `SCHEMA=DRAFT`"));
+    };
+    let schema = schema.strip_prefix("./").unwrap_or(schema);
+    Ok((schema.to_string(), parse_draft(draft)?))
+}
+
+/// Parse a size in bytes, optionally with a `K`, `M` or `G` suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (number, multiplier) = match size.as_bytes().last() {
+        Some(b'K' | b'k') => (&size[..size.len() - 1], 1 << 10),
+        Some(b'M' | b'm') => (&size[..size.len() - 1], 1 << 20),
+        Some(b'G' | b'g') => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size `{size}`, expected e.g. `1048576`, `512K` or `1G`"))
+}
+
+/// Parse a maximum nesting depth, which is at most [`MAX_DEPTH`].
+fn parse_depth(depth: &str) -> Result<usize, String> {
+    depth
+        .parse::<usize>()
+        .ok()
+        .filter(|&depth| depth <= MAX_DEPTH)
+        .ok_or_else(|| format!("Invalid depth `{depth}`, expected at most {MAX_DEPTH}"))
+}
+
+/// Build the thread pool for validation with `jobs` threads, default to the number of CPUs. Threads have large stacks, since parsing and validating deeply nested instances recurse.
+fn thread_pool(jobs: Option<usize>) -> Result<ThreadPool, ThreadPoolBuildError> {
+    ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .stack_size(STACK_SIZE)
+        .build()
+}
+
 /// Parse command line arguments and return configuration options.
 pub fn get_config() -> Config {
-    let args = Args::parse();
+    let args = parse_args(std::env::args_os()).unwrap_or_else(|error| error.exit());
     args.into()
 }
 
+/// Parse given command line arguments.
+fn parse_args<I, T>(args: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args = Args::try_parse_from(args)?;
+    // Subcommands take precedence, so that `scheval receipts.json schemas` doesn't validate a file named `schemas`
+    if let (Some(_), Some(file)) = (&args.command, args.files.first()) {
+        return Err(Args::command().error(
+            ErrorKind::ArgumentConflict,
+            format!(
+                "File `{}` can't be given with a subcommand. To validate a file named like a subcommand, prefix it with `./`",
+                file.to_string_lossy()
+            ),
+        ));
+    }
+    Ok(args)
+}
+
+/// Files and directories marking the root of a workspace, for `--find-base`.
+const ROOT_MARKERS: [&str; 6] = [
+    ".vscode",
+    ".idea",
+    ".git",
+    "Cargo.toml",
+    "pyproject.toml",
+    "package.json",
+];
+
+/// Get base directories to run scheval with, relative to the working directory so that paths in reports are too. Default to `"."`.
+pub fn get_bases(config: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let current = std::env::current_dir()?.canonicalize()?;
+    let bases = if config.find_base {
+        let found = current
+            .ancestors()
+            .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()));
+        let Some(found) = found else {
+            eprintln!("No workspace root found, using the working directory");
+            return Ok(vec![".".to_string()]);
+        };
+        vec![found.to_path_buf()]
+    } else if config.bases.is_empty() {
+        return Ok(vec![".".to_string()]);
+    } else {
+        let mut bases = Vec::new();
+        for base in &config.bases {
+            match base.canonicalize() {
+                Ok(canonical) if canonical.is_dir() => bases.push(canonical),
+                Ok(_) => {
+                    return Err(
+                        format!("Base `{}` is not a directory", base.to_string_lossy()).into(),
+                    )
+                }
+                Err(error) => {
+                    return Err(
+                        format!("Invalid base `{}`: {error}", base.to_string_lossy()).into(),
+                    )
+                }
+            }
+        }
+        bases
+    };
+    if config.command == Some(Command::Watch) && bases.len() > 1 {
+        return Err("Watch mode supports a single base directory".into());
+    }
+    Ok(bases
+        .iter()
+        .map(|base| relative_path(&current, base).to_string_lossy().into_owned())
+        .collect())
+}
+
 // JSON Schema Validation
 
 /// A JSON Schema.
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Schema {
     Local(PathBuf),
     Remote(String),
     Inline(Value),
+    /// A schema bundled into the binary, identified by name.
+    Builtin(String),
 }
 
 impl Schema {
-    /// Resolve the schema to a JSON value, **consuming `self`**.
-    fn resolve(self, base: &Path) -> Result<Value, Box<dyn Error>> {
+    /// Get the URI that relative `$ref`s in local and remote schemas are resolved against, i.e. their location.
+    fn base_uri(&self, base: &Path) -> Option<Url> {
+        match self {
+            Schema::Local(path) => Url::from_file_path(base.join(path).canonicalize().ok()?).ok(),
+            Schema::Remote(url) => Url::parse(url).ok(),
+            _ => None,
+        }
+    }
+
+    /// Resolve the schema to a JSON value, **consuming `self`**. The content is left as is, so use [`Schema::base_uri`] to resolve relative `$ref`s.
+    fn resolve(self, base: &Path, retriever: &SchemaRetriever) -> Result<Value, Box<dyn Error>> {
         use Schema::*;
         match self {
             Local(path) => {
-                let path = base.join(path);
-                let json = read_json(&path)??;
-                Ok(json)
+                let path = base.join(path).canonicalize()?;
+                Ok(read_json(&path, retriever.staged())??)
             }
-            Remote(_) => Err("Remote schema is not supported yet".into()),
+            Remote(url) => retriever.retrieve_url(&url),
             Inline(value) => Ok(value),
+            #[cfg(feature = "builtin-schemas")]
+            Builtin(name) => include::get_builtin_schema(&name)
+                .ok_or_else(|| format!("Unknown built-in schema `{name}`").into()),
+            #[cfg(not(feature = "builtin-schemas"))]
+            Builtin(_) => Err("Built-in schemas require the `builtin-schemas` feature".into()),
         }
     }
 }
 
+impl Schema {
+    /// Rank of the variant, for ordering.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Local(_) => 0,
+            Self::Remote(_) => 1,
+            Self::Inline(_) => 2,
+            Self::Builtin(_) => 3,
+        }
+    }
+}
+
+/// Schemas are ordered by kind (local, remote, inline, built-in), then by path, URL, serialized content or name.
+impl Ord for Schema {
+    fn cmp(&self, other: &Self) -> Ordering {
+        use Schema::*;
+        match (self, other) {
+            (Local(a), Local(b)) => a.cmp(b),
+            (Remote(a), Remote(b)) | (Builtin(a), Builtin(b)) => a.cmp(b),
+            (Inline(a), Inline(b)) => a.to_string().cmp(&b.to_string()),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Schema {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Display for Schema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Local(path) => write!(f, "{}", path.to_string_lossy()),
             Self::Remote(url) => write!(f, "{}", url),
             Self::Inline(_) => write!(f, "<inline schema>"),
+            Self::Builtin(name) => write!(f, "<builtin schema: {name}>"),
         }
     }
 }
 
-/// Read JSON file from given path.
-fn read_json(path: &Path) -> Result<serde_json::Result<Value>, Box<dyn Error>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    Ok(serde_json::from_reader(reader))
+/// Read file from given path, or from the git index if `staged` is given and the path is inside the repository.
+fn read_file(path: &Path, staged: Option<&Staged>) -> io::Result<Vec<u8>> {
+    staged
+        .and_then(|staged| staged.read(path))
+        .unwrap_or_else(|| fs::read(path))
+}
+
+/// Read JSON file from given path, or from the git index if `staged` is given (see [`read_file`]).
+fn read_json(
+    path: &Path,
+    staged: Option<&Staged>,
+) -> Result<serde_json::Result<Value>, Box<dyn Error>> {
+    // Parsing from a slice is much faster than from a reader
+    Ok(serde_json::from_slice(&read_file(path, staged)?))
+}
+
+/// Timeout for fetching remote schemas, so that an unreachable host doesn't hang the run.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fetch JSON from given URL.
+fn fetch_json(url: &str) -> Result<Value, Box<dyn Error>> {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    let agent = AGENT.get_or_init(|| ureq::AgentBuilder::new().timeout(FETCH_TIMEOUT).build());
+    let json = agent.get(url).call()?.into_json()?;
+    Ok(json)
 }
 
 /// Validate a JSON instance against a JSON Schema.
@@ -131,23 +528,46 @@ pub fn validate_instance(
     validator: &jsonschema::Validator,
     instance: &Path,
 ) -> Result<bool, Box<dyn Error>> {
-    let mut success = true;
-    let instance_json = read_json(instance)??;
-    let mut errors = validator.iter_errors(&instance_json);
-    let filename = instance.to_string_lossy();
-    if let Some(first) = errors.next() {
-        success = false;
-        println!("- `{filename}` - {FAILURE}INVALID{FAILURE:#}. Errors:");
-        println!("  1. {first}");
-        for (i, error) in errors.enumerate() {
-            println!("  {}. {error}", i + 2);
-        }
-    } else {
-        println!("- `{filename}` - {SUCCESS}VALID{SUCCESS:#}");
-    }
+    let (success, report) = report_instance(validator, instance)?;
+    print!("{report}");
     Ok(success)
 }
 
+/// Validate a JSON instance against a JSON Schema, returning whether it's valid and the report to print.
+fn report_instance(
+    validator: &jsonschema::Validator,
+    instance: &Path,
+) -> Result<(bool, String), Box<dyn Error>> {
+    let instance_json = parse_instance(
+        instance,
+        &read_instance(instance, &Limits::default(), None)?,
+    )?;
+    let errors: Vec<_> = validator
+        .iter_errors(&instance_json)
+        .map(|error| error.to_string())
+        .collect();
+    format_report(&instance.to_string_lossy(), &errors)
+}
+
+/// Format the report of an instance at `filename` with validation `errors`, returning whether it's valid and the report to print.
+fn format_report(filename: &str, errors: &[String]) -> Result<(bool, String), Box<dyn Error>> {
+    let mut errors = errors.iter();
+    let mut report = String::new();
+    let Some(first) = errors.next() else {
+        writeln!(report, "- `{filename}` - {SUCCESS}VALID{SUCCESS:#}")?;
+        return Ok((true, report));
+    };
+    writeln!(
+        report,
+        "- `{filename}` - {FAILURE}INVALID{FAILURE:#}. Errors:"
+    )?;
+    writeln!(report, "  1. {first}")?;
+    for (i, error) in errors.enumerate() {
+        writeln!(report, "  {}. {error}", i + 2)?;
+    }
+    Ok((false, report))
+}
+
 // Helper Functions
 
 /// Try to relativize `target` path, resort to absolute path if failed. Note that given paths must be canonicalized.
@@ -160,79 +580,853 @@ fn regularize(base: &Path, target: &Path) -> PathBuf {
 
 /// Extend `associations` with `new_associations`, **consuming `new_associations`**.
 fn extend(
-    associations: &mut HashMap<Schema, HashSet<PathBuf>>,
-    new_associations: HashMap<Schema, HashSet<PathBuf>>,
+    associations: &mut BTreeMap<Schema, BTreeSet<PathBuf>>,
+    new_associations: BTreeMap<Schema, BTreeSet<PathBuf>>,
 ) {
     for (schema, instances) in new_associations {
-        associations
-            .entry(schema)
-            .or_insert_with(HashSet::new)
-            .extend(instances);
+        associations.entry(schema).or_default().extend(instances);
+    }
+}
+
+/// Get the path to `target` relative to `dir`, using `..` if needed, or `target` itself if they share no prefix. Note that given paths must be canonicalized.
+fn relative_path(dir: &Path, target: &Path) -> PathBuf {
+    let (mut dir_components, mut target_components) = (dir.components(), target.components());
+    if dir_components.next() != target_components.next() {
+        // On different drives
+        return target.to_path_buf();
+    }
+    let (dir_components, target_components): (Vec<_>, Vec<_>) =
+        (dir_components.collect(), target_components.collect());
+    let common = dir_components
+        .iter()
+        .zip(&target_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let relative: PathBuf =
+        std::iter::repeat_n(Component::ParentDir, dir_components.len() - common)
+            .chain(target_components[common..].iter().copied())
+            .collect();
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+/// Get the name of `schema` in reports. Local schemas are shown with `base`, so that the path is relative to the working directory.
+fn schema_name(schema: &Schema, base: &Path) -> String {
+    match schema {
+        Schema::Local(path) if base != Path::new(".") => {
+            base.join(path).to_string_lossy().into_owned()
+        }
+        _ => schema.to_string(),
     }
 }
 
 // Main Logic
 
-/// Run scheval with given configuration.
-pub fn run(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
-    let mut success = true;
-    let mut associations = HashMap::new();
+/// Create an index of `base` respecting excludes, shared by all including features. Files are listed from the git index if `config.staged`.
+fn file_index(config: &Config, base: &str) -> Result<Arc<FileIndex>, Box<dyn Error>> {
+    let mut excludes = config.excludes.clone();
+    // Never look for instances in the validation cache, or vendored copies named after their URLs
+    excludes.push(format!("/{CACHE_DIR}/"));
+    let vendor = vendor_dir(config, Path::new(base)).canonicalize();
+    if let (Ok(vendor), Ok(root)) = (vendor, Path::new(base).canonicalize()) {
+        if let Some(relative) = vendor
+            .strip_prefix(root)
+            .ok()
+            .filter(|relative| !relative.as_os_str().is_empty())
+        {
+            excludes.push(format!(
+                "/{}/",
+                relative.to_string_lossy().replace('\\', "/")
+            ));
+        }
+    }
+    let index = FileIndex::with_excludes(base, &excludes);
+    if !config.staged {
+        return Ok(Arc::new(index));
+    }
+    let staged = Staged::load(Path::new(base))?;
+    Ok(Arc::new(index.with_staged(Arc::new(staged))))
+}
+
+/// Collect associations from all enabled including features, querying the same `index`, along with drafts of schemas (identified by [`lock_key`]) given by the including features.
+fn collect_associations(
+    config: &Config,
+    index: &Arc<FileIndex>,
+) -> (BTreeMap<Schema, BTreeSet<PathBuf>>, include::Drafts) {
+    let mut associations = BTreeMap::new();
+    let mut drafts = Vec::new();
     if config.vscode {
-        let inc = include::Vscode::with_base(base);
+        let inc = include::Vscode::with_index(Arc::clone(index));
         let vscode_associations = inc.get_associations();
         extend(&mut associations, vscode_associations);
     }
+    if config.extension {
+        let inc = include::Extension::with_index(Arc::clone(index));
+        let extension_associations = inc.get_associations();
+        extend(&mut associations, extension_associations);
+    }
+    if config.jetbrains {
+        let inc = include::Jetbrains::with_index(Arc::clone(index));
+        let (jetbrains_associations, jetbrains_drafts) = inc.get_associations_with_drafts();
+        extend(&mut associations, jetbrains_associations);
+        drafts.extend(jetbrains_drafts);
+    }
+    if config.manifest {
+        let inc = include::Manifest::with_index(Arc::clone(index));
+        let manifest_associations = inc.get_associations();
+        extend(&mut associations, manifest_associations);
+    }
     if config.suffix {
-        let inc = include::Suffix::with_base(base);
+        let inc = include::Suffix::with_index(Arc::clone(index));
         let suffix_associations = inc.get_associations();
         extend(&mut associations, suffix_associations);
     }
-    let base = Path::new(base);
+    if config.catalog {
+        let inc = include::Catalog::with_index(Arc::clone(index));
+        let inc = match &config.catalog_path {
+            Some(path) => inc.with_catalog(path),
+            None => inc,
+        };
+        let catalog_associations = inc.get_associations();
+        extend(&mut associations, catalog_associations);
+    }
+    if config.builtin {
+        #[cfg(feature = "builtin-schemas")]
+        {
+            let inc = include::Builtin::with_index(Arc::clone(index));
+            let builtin_associations = inc.get_associations();
+            extend(&mut associations, builtin_associations);
+        }
+        #[cfg(not(feature = "builtin-schemas"))]
+        eprintln!("`builtin` requires the `builtin-schemas` feature, skipping");
+    }
+    (associations, drafts)
+}
+
+/// Get the vendor directory from configuration.
+fn vendor_dir(config: &Config, base: &Path) -> PathBuf {
+    config
+        .vendor_dir
+        .clone()
+        .unwrap_or_else(|| base.join(DEFAULT_VENDOR_DIR))
+}
+
+/// A resolved schema, with all associations sharing its identity merged.
+struct ResolvedSchema {
+    /// Names of the merged schemas.
+    names: Vec<String>,
+    /// Local files the merged schemas are read from.
+    files: Vec<PathBuf>,
+    /// Identity of the schema (see [`schema_identity`]).
+    identity: String,
+    json: Value,
+    /// URI that relative `$ref`s are resolved against, if any.
+    base_uri: Option<Url>,
+    /// Draft forced for the schema, if any.
+    draft: Option<Draft>,
+    instances: BTreeSet<PathBuf>,
+    /// Digest of the schema for caching results, if caching is enabled.
+    digest: Option<String>,
+    /// Error resolving the schema, reported in place of compiling it.
+    error: Option<String>,
+}
+
+impl ResolvedSchema {
+    /// Get canonicalized paths to local files the schema is read from, including local `$ref`s transitively, without fetching remote ones.
+    fn dependencies(&self, retriever: &SchemaRetriever) -> HashSet<PathBuf> {
+        let mut dependencies: HashSet<_> = self
+            .files
+            .iter()
+            .filter_map(|file| file.canonicalize().ok())
+            .collect();
+        dependencies.extend(
+            local_refs(&self.json, self.base_uri.as_ref(), retriever)
+                .into_iter()
+                .filter_map(|path| path.canonicalize().ok()),
+        );
+        dependencies
+    }
+
+    /// Compile the schema, or report the error resolving it.
+    fn compile(
+        &self,
+        retriever: &SchemaRetriever,
+        meta_validator: &Option<jsonschema::Validator>,
+    ) -> Result<jsonschema::Validator, String> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        compile_schema(
+            self.json.clone(),
+            self.base_uri.as_ref(),
+            self.draft,
+            retriever,
+            meta_validator,
+        )
+    }
+}
+
+/// Get the identity of a resolved schema at `base_uri` under the forced `draft`. Schemas without relative `$ref`s are identified by their content alone, regardless of their location.
+fn schema_identity(schema: &Value, base_uri: Option<&Url>, draft: Option<Draft>) -> String {
+    let mut schema = Cow::Borrowed(schema);
+    let mut location = "";
+    if has_relative_refs(&schema) {
+        location = base_uri.map_or("", Url::as_str);
+    } else if let Value::Object(object) = schema.to_mut() {
+        object.remove("$id");
+        object.remove("id");
+    }
+    format!(
+        "{draft:?}:{location}:{}",
+        sha256_hex(schema.to_string().as_bytes())
+    )
+}
+
+/// Get the draft forced for the schema identified by `key` (see [`lock_key`]), if any. Drafts given by `--schema-draft` take precedence over `drafts` given by including features.
+fn forced_draft(config: &Config, drafts: &[(String, Draft)], key: &str) -> Option<Draft> {
+    config
+        .schema_drafts
+        .iter()
+        .chain(drafts)
+        .find(|(schema, _)| schema == key)
+        .map(|(_, draft)| *draft)
+        .or(config.draft)
+}
+
+/// Build a validator for the custom meta-schema, if given.
+fn build_meta_validator(
+    config: &Config,
+    retriever: &SchemaRetriever,
+) -> Result<Option<jsonschema::Validator>, Box<dyn Error>> {
+    let Some(path) = &config.meta_schema else {
+        return Ok(None);
+    };
+    let meta_schema = read_json(path, None)??;
+    let validator = jsonschema::options()
+        .with_retriever(retriever.clone())
+        .build(&meta_schema)
+        .map_err(|error| format!("Invalid meta-schema `{}`: {error}", path.to_string_lossy()))?;
+    Ok(Some(validator))
+}
+
+/// Build the retriever for `$ref`s with given configuration, reading local files from the same source as `index`.
+fn build_retriever(
+    config: &Config,
+    base: &Path,
+    index: &FileIndex,
+) -> Result<SchemaRetriever, Box<dyn Error>> {
+    let vendored = Vendored::load(&vendor_dir(config, base))?;
+    Ok(SchemaRetriever::default()
+        .with_staged(index.staged().cloned())
+        .with_schema_dirs(&config.schema_dirs, index)
+        .with_rewrites(&config.rewrites)
+        .with_vendored(vendored))
+}
+
+/// Resolve schemas in order, merging those with the same identity into the first one so that each is compiled only once. `inspect` is called with the key (see [`lock_key`]), content and base URI of every schema before merging. Schemas failing to be resolved (e.g. remote ones when offline) are kept with the error, to be reported in place of their instances.
+fn resolve_associations(
+    config: &Config,
+    associations: BTreeMap<Schema, BTreeSet<PathBuf>>,
+    drafts: &[(String, Draft)],
+    base: &Path,
+    retriever: &SchemaRetriever,
+    mut inspect: impl FnMut(String, &Value, Option<&Url>) -> Result<(), Box<dyn Error>>,
+) -> Result<Vec<ResolvedSchema>, Box<dyn Error>> {
+    let mut resolved: Vec<ResolvedSchema> = Vec::new();
+    let mut identities: HashMap<String, usize> = HashMap::new();
     for (schema, instances) in associations {
-        println!("Schema `{schema}`:");
-        let schema_json = schema.resolve(base)?;
-        let validator = match jsonschema::validator_for(&schema_json) {
-            Ok(validator) => validator,
+        let name = schema_name(&schema, base);
+        let key = lock_key(&schema);
+        let draft = forced_draft(config, drafts, &key);
+        let file = match &schema {
+            Schema::Local(path) => Some(base.join(path)),
+            _ => None,
+        };
+        let base_uri = schema.base_uri(base);
+        let schema_json = match schema.resolve(base, retriever) {
+            Ok(schema_json) => schema_json,
             Err(error) => {
-                println!("{FAILURE}Invalid schema{FAILURE:#}: {error}\n");
-                success = false;
+                resolved.push(ResolvedSchema {
+                    names: vec![name],
+                    files: file.into_iter().collect(),
+                    identity: format!("unresolved:{key}"),
+                    json: Value::Null,
+                    base_uri,
+                    draft,
+                    instances,
+                    digest: None,
+                    error: Some(format!(
+                        "{FAILURE}Failed to resolve schema{FAILURE:#}: {error}\n"
+                    )),
+                });
                 continue;
             }
         };
-        for instance in instances {
+        inspect(key, &schema_json, base_uri.as_ref())?;
+        let identity = schema_identity(&schema_json, base_uri.as_ref(), draft);
+        if let Some(&index) = identities.get(&identity) {
+            let existing = &mut resolved[index];
+            existing.names.push(name);
+            existing.files.extend(file);
+            existing.instances.extend(instances);
+            continue;
+        }
+        identities.insert(identity.clone(), resolved.len());
+        resolved.push(ResolvedSchema {
+            names: vec![name],
+            files: file.into_iter().collect(),
+            identity,
+            json: schema_json,
+            base_uri,
+            draft,
+            instances,
+            digest: None,
+            error: None,
+        });
+    }
+    Ok(resolved)
+}
+
+/// Run scheval with given configuration. Reports are printed in order of schemas, then instances.
+pub fn run(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    run_with_files(config, base, listed_files(config)?.as_ref())
+}
+
+/// Run scheval with given configuration, restricted to `listed` files (see [`listed_files`]) if given. Use this to run with multiple bases, so that the list is read only once.
+pub fn run_with_files(
+    config: &Config,
+    base: &str,
+    listed: Option<&HashSet<PathBuf>>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut success = true;
+    let index = file_index(config, base)?;
+    let (associations, drafts) = collect_associations(config, &index);
+    let base = Path::new(base);
+    let retriever = build_retriever(config, base, &index)?;
+    let meta_validator = build_meta_validator(config, &retriever)?;
+    let meta_schema = match &config.meta_schema {
+        Some(path) if config.cache => Some(read_json(path, None)??),
+        _ => None,
+    };
+    let cache = config.cache.then(|| Cache::load(&base.join(CACHE_DIR)));
+    let limits = Limits::new(config.max_file_size, config.max_depth);
+    let mut lockfile = Lockfile::default();
+    let mut resolved = resolve_associations(
+        config,
+        associations,
+        &drafts,
+        base,
+        &retriever,
+        |key, schema_json, base_uri| {
+            if config.locked {
+                lockfile.insert(key, schema_json);
+                lockfile.insert_refs(schema_json, base_uri, base, &retriever)?;
+            }
+            Ok(())
+        },
+    )?;
+    if let Some(rev) = &config.since {
+        retain_affected(
+            &mut resolved,
+            &git::changed_files(base, rev, config.staged)?,
+            config,
+            &index,
+            &retriever,
+        );
+        if resolved.is_empty() {
+            eprintln!("No instances affected by changes since `{rev}`");
+        }
+    }
+    if let Some(files) = listed {
+        retain_affected(&mut resolved, files, config, &index, &retriever);
+        if resolved.is_empty() {
+            eprintln!("No instances affected by given files");
+        }
+    }
+    if config.cache {
+        for resolved in resolved
+            .iter_mut()
+            .filter(|resolved| resolved.error.is_none())
+        {
+            // Results can't be cached if referenced schemas can't be retrieved, which is reported on compilation
+            resolved.digest = schema_digest(
+                &resolved.identity,
+                &resolved.json,
+                resolved.base_uri.as_ref(),
+                meta_schema.as_ref(),
+                base,
+                &retriever,
+            )
+            .ok();
+        }
+    }
+    let pool = thread_pool(config.jobs)?;
+    let (valid, output) = pool.install(|| {
+        validate_resolved(
+            resolved,
+            base,
+            &retriever,
+            &meta_validator,
+            cache.as_ref(),
+            &limits,
+        )
+    });
+    print!("{output}");
+    success &= valid;
+    if let Some(cache) = cache {
+        // Results of instances left out are still useful
+        let restricted = config.since.is_some() || listed.is_some();
+        if let Err(error) = cache.save(!restricted) {
+            eprintln!("Failed to write validation cache: {error}");
+        }
+    }
+    if config.locked {
+        success &= verify_lockfile(&lockfile, base)?;
+    }
+    Ok(success)
+}
+
+/// Get canonicalized paths to files given by `config.files` and `config.files_from`, or `None` if neither is given. Missing files are skipped. Standard input is read if `config.files_from` is `-`, so call this only once.
+pub fn listed_files(config: &Config) -> Result<Option<HashSet<PathBuf>>, Box<dyn Error>> {
+    let mut files = config.files.clone();
+    match config.files_from.as_deref() {
+        Some(path) if path == Path::new("-") => {
+            let mut list = Vec::new();
+            io::stdin().read_to_end(&mut list)?;
+            files.extend(split_file_list(&list));
+        }
+        Some(path) => files.extend(split_file_list(&fs::read(path)?)),
+        None if files.is_empty() => return Ok(None),
+        None => {}
+    }
+    let files = files
+        .into_iter()
+        .filter_map(|file| match file.canonicalize() {
+            Ok(file) => Some(file),
+            Err(error) => {
+                eprintln!("Skipping `{}`: {error}", file.to_string_lossy());
+                None
+            }
+        })
+        .collect();
+    Ok(Some(files))
+}
+
+/// Split a list of paths separated by NUL characters if any, or newlines otherwise.
+fn split_file_list(list: &[u8]) -> Vec<PathBuf> {
+    let separator = if list.contains(&0) { 0 } else { b'\n' };
+    list.split(|&byte| byte == separator)
+        .map(|path| String::from_utf8_lossy(path))
+        .map(|path| PathBuf::from(path.strip_suffix('\r').unwrap_or(&path)))
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect()
+}
+
+/// Check whether the file at `path` (canonicalized) defines associations for the including features enabled in `config`, relative to the base directory of `index`.
+fn is_config_file(path: &Path, config: &Config, index: &FileIndex) -> bool {
+    let Ok(relative) = path.strip_prefix(index.base()) else {
+        return false;
+    };
+    let is = |file: &str| relative == Path::new(file);
+    config.vscode && is(".vscode/settings.json")
+        || config.jetbrains && is(".idea/jsonSchemas.xml")
+        || config.manifest && (is("Cargo.toml") || is("pyproject.toml"))
+        || config.extension
+            && relative
+                .file_name()
+                .is_some_and(|name| name == "package.json")
+            && !relative
+                .components()
+                .any(|component| component.as_os_str() == "node_modules")
+            && declares_json_validation(path, index)
+}
+
+/// Retain schemas affected by `changed` files (canonicalized paths) with all their instances, and changed instances of other schemas. Everything is affected if files defining associations, the catalog or the custom meta-schema changed.
+fn retain_affected(
+    resolved: &mut Vec<ResolvedSchema>,
+    changed: &HashSet<PathBuf>,
+    config: &Config,
+    index: &FileIndex,
+    retriever: &SchemaRetriever,
+) {
+    let environment: Vec<_> = config
+        .catalog_path
+        .iter()
+        .chain(&config.meta_schema)
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+    if changed
+        .iter()
+        .any(|path| is_config_file(path, config, index) || environment.contains(path))
+    {
+        return;
+    }
+    resolved.retain_mut(|resolved| {
+        if !resolved.dependencies(retriever).is_disjoint(changed) {
+            return true;
+        }
+        resolved.instances.retain(|instance| {
+            index
+                .base()
+                .join(instance)
+                .canonicalize()
+                .is_ok_and(|instance| changed.contains(&instance))
+        });
+        !resolved.instances.is_empty()
+    });
+}
+
+/// Compile resolved schemas and validate their instances in parallel, returning whether all are valid and the reports to print in order. Results found in `cache` are reused, and schemas are only compiled if needed.
+fn validate_resolved(
+    resolved: Vec<ResolvedSchema>,
+    base: &Path,
+    retriever: &SchemaRetriever,
+    meta_validator: &Option<jsonschema::Validator>,
+    cache: Option<&Cache>,
+    limits: &Limits,
+) -> (bool, String) {
+    let mut success = true;
+    let validators: Vec<OnceLock<_>> = resolved.iter().map(|_| OnceLock::new()).collect();
+    let compile = |index: usize| {
+        validators[index].get_or_init(|| resolved[index].compile(retriever, meta_validator))
+    };
+    // Compile schemas whose results can't be cached eagerly, so that invalid ones are reported even without instances
+    (0..resolved.len())
+        .into_par_iter()
+        .filter(|&index| {
+            cache.is_none()
+                || resolved[index].digest.is_none()
+                || resolved[index].instances.is_empty()
+        })
+        .for_each(|index| {
+            compile(index);
+        });
+    // Validate instances of all schemas at once, for better load balancing
+    let jobs: Vec<_> = resolved
+        .iter()
+        .enumerate()
+        .flat_map(|(index, resolved)| {
+            resolved
+                .instances
+                .iter()
+                .map(move |instance| (index, instance))
+        })
+        .collect();
+    let mut reports: Vec<Vec<_>> = resolved.iter().map(|_| Vec::new()).collect();
+    let results: Vec<_> = jobs
+        .into_par_iter()
+        .map(|(index, instance)| {
             let instance = base.join(instance);
-            let valid = validate_instance(&validator, &instance)?;
-            success &= valid;
+            let check = || -> Result<Option<(bool, String)>, Box<dyn Error>> {
+                let content = read_instance(&instance, limits, retriever.staged())?;
+                let filename = instance.to_string_lossy();
+                let key = cache
+                    .and(resolved[index].digest.as_deref())
+                    .map(|digest| result_key(digest, &content, is_jsonc(&instance)));
+                if let Some(errors) = key.as_deref().and_then(|key| cache?.get(key)) {
+                    return Ok(Some(format_report(&filename, errors)?));
+                }
+                // Reported once for the schema
+                let Ok(validator) = compile(index) else {
+                    return Ok(None);
+                };
+                let instance_json = parse_instance(&instance, &content)?;
+                let errors: Vec<_> = validator
+                    .iter_errors(&instance_json)
+                    .map(|error| error.to_string())
+                    .collect();
+                let report = format_report(&filename, &errors)?;
+                if let (Some(cache), Some(key)) = (cache, key) {
+                    cache.insert(key, errors);
+                }
+                Ok(Some(report))
+            };
+            let report = check().map_err(|error| error.to_string());
+            (index, instance, report)
+        })
+        .collect();
+    for (index, instance, report) in results {
+        reports[index].push((instance, report));
+    }
+    let mut output = String::new();
+    for ((resolved, validator), reports) in resolved.iter().zip(&validators).zip(reports) {
+        let _ = writeln!(output, "Schema `{}`:", resolved.names.join("`, `"));
+        if let Some(Err(error)) = validator.get() {
+            let _ = writeln!(output, "{error}");
+            success = false;
+            continue;
+        }
+        for (instance, report) in reports {
+            match report {
+                Ok(Some((valid, report))) => {
+                    output.push_str(&report);
+                    success &= valid;
+                }
+                Ok(None) => {}
+                // Instances that can't be read or parsed, or exceed the limits, fail on their own
+                Err(error) => {
+                    let _ = writeln!(
+                        output,
+                        "- `{}` - {FAILURE}ERROR{FAILURE:#}: {error}",
+                        instance.to_string_lossy()
+                    );
+                    success = false;
+                }
+            }
+        }
+        output.push('\n');
+    }
+    (success, output)
+}
+
+/// Compile a resolved schema at `base_uri`, or return the report of why it's invalid.
+fn compile_schema(
+    mut schema_json: Value,
+    base_uri: Option<&Url>,
+    draft: Option<Draft>,
+    retriever: &SchemaRetriever,
+    meta_validator: &Option<jsonschema::Validator>,
+) -> Result<jsonschema::Validator, String> {
+    if let Some(meta_validator) = meta_validator {
+        let errors: Vec<_> = meta_validator.iter_errors(&schema_json).collect();
+        if !errors.is_empty() {
+            let mut report =
+                format!("{FAILURE}Invalid schema{FAILURE:#} against the custom meta-schema:\n");
+            for (i, error) in errors.iter().enumerate() {
+                report.push_str(&format!("  {}. {error}\n", i + 1));
+            }
+            return Err(report);
+        }
+    }
+    let invalid = |error: &dyn Display| format!("{FAILURE}Invalid schema{FAILURE:#}: {error}\n");
+    let mut options = jsonschema::options();
+    options.with_retriever(retriever.clone());
+    // Only force the draft if the schema doesn't declare one
+    let draft = draft.filter(|_| schema_json.get("$schema").is_none());
+    if let Some(draft) = draft {
+        options.with_draft(draft);
+        if draft == Draft::Draft4 {
+            set_draft4_id(&mut schema_json);
+        }
+    }
+    // Inline schemas are located by their `$id`
+    let base_uri = base_uri.cloned().or_else(|| {
+        let id = schema_json.get("$id").or_else(|| schema_json.get("id"));
+        Url::parse(id?.as_str()?).ok()
+    });
+    let Some(base_uri) = base_uri.filter(|_| has_relative_refs(&schema_json)) else {
+        return options.build(&schema_json).map_err(|error| invalid(&error));
+    };
+    // The base URI of the root schema is its `$id`, which is ignored next to `$ref` before Draft 2019-09. Register the schema under its location instead, and compile a reference to it
+    let draft = match draft {
+        Some(draft) => draft,
+        None => Draft::default()
+            .detect(&schema_json)
+            .map_err(|error| invalid(&error))?,
+    };
+    draft_meta_validator(draft)
+        .validate(&schema_json)
+        .map_err(|error| invalid(&error))?;
+    options
+        .with_draft(draft)
+        .with_resource(base_uri.as_str(), draft.create_resource(schema_json))
+        .build(&serde_json::json!({ "$ref": base_uri.as_str() }))
+        .map_err(|error| invalid(&error))
+}
+
+/// Get the validator for the meta-schema of `draft`, which `jsonschema` only checks the root schema against.
+fn draft_meta_validator(draft: Draft) -> &'static jsonschema::Validator {
+    static VALIDATORS: OnceLock<HashMap<Draft, jsonschema::Validator>> = OnceLock::new();
+    let validators = VALIDATORS.get_or_init(|| {
+        [
+            (Draft::Draft4, &*referencing::meta::DRAFT4),
+            (Draft::Draft6, &*referencing::meta::DRAFT6),
+            (Draft::Draft7, &*referencing::meta::DRAFT7),
+            (Draft::Draft201909, &*referencing::meta::DRAFT201909),
+            (Draft::Draft202012, &*referencing::meta::DRAFT202012),
+        ]
+        .into_iter()
+        .map(|(draft, meta_schema)| {
+            let validator = jsonschema::options()
+                .with_draft(draft)
+                .build(meta_schema)
+                .expect("Meta-schemas are valid");
+            (draft, validator)
+        })
+        .collect()
+    });
+    &validators[&draft]
+}
+
+/// Verify that `lockfile` matches the one on disk, reporting any differences.
+fn verify_lockfile(lockfile: &Lockfile, base: &Path) -> Result<bool, Box<dyn Error>> {
+    let Some(locked) = Lockfile::load(base)? else {
+        println!(
+            "{FAILURE}Lockfile not found{FAILURE:#}: Run `scheval lock` to create `{LOCKFILE}`"
+        );
+        return Ok(false);
+    };
+    let differences = lockfile.diff(&locked);
+    if differences.is_empty() {
+        println!("{SUCCESS}Lockfile up to date{SUCCESS:#}");
+        return Ok(true);
+    }
+    println!("{FAILURE}Lockfile out of date{FAILURE:#}: Run `scheval lock` to update `{LOCKFILE}`");
+    for difference in differences {
+        println!("- {difference}");
+    }
+    Ok(false)
+}
+
+/// Record a content hash for every schema used with given configuration into the lockfile.
+pub fn lock(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    let index = file_index(config, base)?;
+    let (associations, _) = collect_associations(config, &index);
+    let base = Path::new(base);
+    let retriever = build_retriever(config, base, &index)?;
+    let mut lockfile = Lockfile::default();
+    for schema in associations.into_keys() {
+        let key = lock_key(&schema);
+        let base_uri = schema.base_uri(base);
+        let schema_json = schema.resolve(base, &retriever)?;
+        lockfile.insert(key, &schema_json);
+        lockfile.insert_refs(&schema_json, base_uri.as_ref(), base, &retriever)?;
+    }
+    lockfile.write(base)?;
+    println!(
+        "{SUCCESS}Locked{SUCCESS:#} {} schema(s) into `{LOCKFILE}`",
+        lockfile.len()
+    );
+    Ok(true)
+}
+
+/// Validate all discovered schemas against their meta-schemas, reporting all problems, even if no instance uses them.
+pub fn schemas(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    let index = file_index(config, base)?;
+    let (associations, drafts) = collect_associations(config, &index);
+    let mut schemas: BTreeSet<Schema> = associations.into_keys().collect();
+    let base = Path::new(base);
+    schemas.extend(meta::find_schema_files(&index, &config.schema_dirs));
+    let retriever = build_retriever(config, base, &index)?;
+    let meta_validator = build_meta_validator(config, &retriever)?;
+    let mut checker = MetaChecker::new(&retriever);
+    let (mut errors, mut warnings) = (0, 0);
+    let count = schemas.len();
+    for schema in schemas {
+        let name = schema_name(&schema, base);
+        println!("Schema `{name}`:");
+        let key = lock_key(&schema);
+        let draft = forced_draft(config, &drafts, &key);
+        // Source text of local schemas, for locating problems
+        let source = match &schema {
+            Schema::Local(path) => read_file(&base.join(path), retriever.staged())
+                .ok()
+                .and_then(|source| String::from_utf8(source).ok()),
+            _ => None,
+        };
+        let base_uri = schema.base_uri(base);
+        let schema_json = match schema.resolve(base, &retriever) {
+            Ok(schema_json) => schema_json,
+            Err(error) => {
+                println!("- {FAILURE}ERROR{FAILURE:#}: Failed to resolve schema: {error}\n");
+                errors += 1;
+                continue;
+            }
+        };
+        let mut problems = checker.check(&schema_json, base_uri.as_ref(), draft);
+        if let Some(meta_validator) = &meta_validator {
+            problems.extend(meta_validator.iter_errors(&schema_json).map(|error| {
+                let pointer = error.instance_path.to_string();
+                Problem::error(&pointer, format!("{error} (custom meta-schema)"))
+            }));
+        }
+        if problems.is_empty() {
+            println!("- {SUCCESS}OK{SUCCESS:#}");
+        }
+        for problem in problems {
+            let severity = match problem.severity {
+                Severity::Error => {
+                    errors += 1;
+                    format!("{FAILURE}ERROR{FAILURE:#}")
+                }
+                Severity::Warning => {
+                    warnings += 1;
+                    format!("{WARNING}WARNING{WARNING:#}")
+                }
+            };
+            let pointer = &problem.pointer;
+            let location = source
+                .as_deref()
+                .and_then(|source| meta::locate(source, pointer));
+            match location {
+                Some((line, column)) => println!(
+                    "- `{name}:{line}:{column}` (`#{pointer}`) - {severity}: {}",
+                    problem.message
+                ),
+                None => println!("- `#{pointer}` - {severity}: {}", problem.message),
+            }
         }
         println!();
     }
-    Ok(success)
+    println!("Checked {count} schema(s): {errors} error(s), {warnings} warning(s)");
+    Ok(errors == 0)
 }
 
-#[cfg(test)]
-pub(crate) mod tests_util {
-    use std::path::PathBuf;
+/// Validate instances with given configuration, then re-validate affected ones whenever files change, until interrupted. Instead of full reports, a summary of problems is redrawn on every change.
+pub fn watch(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    watch::watch(config, base)
+}
 
-    /// Public constant for the path to the test data directory.
-    pub const TEST_DIR: &str = "tests/data";
+/// Remove all cached validation results under `base`.
+pub fn clean_cache(_config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    let dir = Path::new(base).join(CACHE_DIR);
+    if cache::clean(&dir)? {
+        println!("Removed validation cache `{}`", dir.to_string_lossy());
+    } else {
+        println!("No validation cache found");
+    }
+    Ok(true)
+}
 
-    /// Create a hashset of PathBuf from a list of paths.
-    pub fn hashset_of_pathbuf(paths: &[&str]) -> std::collections::HashSet<PathBuf> {
-        paths.iter().map(|p| PathBuf::from(p)).collect()
+/// Vendor all remote schemas used with given configuration, including remote `$ref`s transitively.
+pub fn vendor(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    let index = file_index(config, base)?;
+    let (associations, _) = collect_associations(config, &index);
+    let base = Path::new(base);
+    let dir = vendor_dir(config, base);
+    let retriever = SchemaRetriever::default()
+        .with_staged(index.staged().cloned())
+        .with_schema_dirs(&config.schema_dirs, &index)
+        .with_rewrites(&config.rewrites);
+    println!("Vendoring remote schemas into `{}`:", dir.to_string_lossy());
+    let schemas = associations.into_keys().collect();
+    let (count, success) = vendor::vendor_schemas(schemas, base, &retriever, &dir)?;
+    if success {
+        println!("\n{SUCCESS}Vendored{SUCCESS:#} {count} schema(s)");
+    } else {
+        println!("\n{FAILURE}Failed{FAILURE:#} to vendor some schemas, vendored {count} schema(s)");
     }
+    Ok(success)
 }
 
 // Styling
 
-// Colors for success and failure messages
+// Colors for success, failure and warning messages
 /// ANSI color for green
 const GREEN: Color = Color::Ansi(AnsiColor::Green);
 /// ANSI color for red
 const RED: Color = Color::Ansi(AnsiColor::Red);
+/// ANSI color for yellow
+const YELLOW: Color = Color::Ansi(AnsiColor::Yellow);
 /// Style for success messages
 const SUCCESS: Style = Style::new().fg_color(Some(GREEN)).bold();
 /// Style for failure messages
 const FAILURE: Style = Style::new().fg_color(Some(RED)).bold();
+/// Style for warning messages
+const WARNING: Style = Style::new().fg_color(Some(YELLOW)).bold();
 
 /// Styling for clap help messages
 // Adapted from https://github.com/8LWXpg/ptr/blob/83aa1d1814ec98d7854e1f4df52d66b8172f6eda/src/main.rs#L124-L131
@@ -248,3 +1442,200 @@ const CLAP_STYLE: Styles = clap::builder::Styles::styled()
 const ITALIC: Style = Style::new().italic();
 /// Dotted-underline style
 const UNDERLINE: Style = Style::new().effects(clap::builder::styling::Effects::DOTTED_UNDERLINE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::{btreeset_of_pathbuf, temp_dir, TEST_DIR};
+
+    /// Resolve `associations` relative to [`TEST_DIR`] and validate them within `limits`, returning whether all are valid and the reports.
+    fn validate_within(
+        associations: BTreeMap<Schema, BTreeSet<PathBuf>>,
+        limits: &Limits,
+    ) -> (bool, String) {
+        let base = Path::new(TEST_DIR);
+        let retriever = SchemaRetriever::default();
+        let resolved = resolve_associations(
+            &Config::default(),
+            associations,
+            &[],
+            base,
+            &retriever,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        validate_resolved(resolved, base, &retriever, &None, None, limits)
+    }
+
+    /// Resolve `associations` relative to [`TEST_DIR`] and validate them, returning whether all are valid and the reports.
+    fn validate(associations: BTreeMap<Schema, BTreeSet<PathBuf>>) -> (bool, String) {
+        validate_within(associations, &Limits::default())
+    }
+
+    #[test]
+    fn test_shared_schema() {
+        let schema_json = read_json(&Path::new(TEST_DIR).join("receipts.schema.json"), None)
+            .unwrap()
+            .unwrap();
+        let associations = [
+            (
+                Schema::Local(PathBuf::from("receipts.schema.json")),
+                btreeset_of_pathbuf(&["receipts.json", "receipts/1.json"]),
+            ),
+            (
+                Schema::Inline(schema_json),
+                btreeset_of_pathbuf(&["receipts.json", "receipts/2.json"]),
+            ),
+        ]
+        .into();
+        let (valid, output) = validate(associations);
+        assert!(valid);
+        // Merged into one schema, which is compiled once, and validates each instance once
+        assert_eq!(output.matches("Schema `").count(), 1);
+        for instance in ["receipts.json", "receipts/1.json", "receipts/2.json"] {
+            let filename = format!("`{}` - ", Path::new(TEST_DIR).join(instance).display());
+            assert_eq!(output.matches(&filename).count(), 1, "{output}");
+        }
+    }
+
+    #[test]
+    fn test_instance_errors() {
+        let associations = [
+            (
+                Schema::Local(PathBuf::from("receipts.schema.json")),
+                btreeset_of_pathbuf(&["missing.json", "receipts/1.json"]),
+            ),
+            (
+                Schema::Local(PathBuf::from("foo_schema.json")),
+                btreeset_of_pathbuf(&["receipts.json"]),
+            ),
+        ]
+        .into();
+        // Instances exceeding the limits or failing to be read are reported, without stopping the run
+        let (valid, output) = validate_within(associations, &Limits::new(Some(16), None));
+        assert!(!valid);
+        assert_eq!(output.matches("Schema `").count(), 2, "{output}");
+        assert_eq!(output.matches("ERROR").count(), 3, "{output}");
+        assert!(
+            output.contains("exceeding the limit of 16 bytes"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_unresolved_schema() {
+        let associations = [
+            (
+                Schema::Local(PathBuf::from("receipts.schema.json")),
+                btreeset_of_pathbuf(&["receipts/1.json"]),
+            ),
+            (
+                Schema::Remote("http://127.0.0.1:1/schema.json".into()),
+                btreeset_of_pathbuf(&["receipts.json"]),
+            ),
+        ]
+        .into();
+        // Schemas failing to be retrieved are reported, without stopping the run
+        let (valid, output) = validate(associations);
+        assert!(!valid);
+        assert_eq!(output.matches("Schema `").count(), 2, "{output}");
+        assert_eq!(output.matches("VALID").count(), 1, "{output}");
+        assert!(output.contains("Failed to resolve schema"), "{output}");
+    }
+
+    #[test]
+    fn test_draft7_root_ref() {
+        let dir = temp_dir();
+        let base = dir.path();
+        fs::write(
+            base.join("common.json"),
+            r#"{"definitions": {"obj": {"type": "object", "required": ["name"]}}}"#,
+        )
+        .unwrap();
+        fs::write(
+            base.join("schema.json"),
+            r#"{"$schema": "http://json-schema.org/draft-07/schema#", "$ref": "./common.json#/definitions/obj"}"#,
+        )
+        .unwrap();
+        fs::write(base.join("valid.json"), r#"{"name": "Alice"}"#).unwrap();
+        fs::write(base.join("invalid.json"), "{}").unwrap();
+        let associations = [(
+            Schema::Local(PathBuf::from("schema.json")),
+            btreeset_of_pathbuf(&["valid.json", "invalid.json"]),
+        )]
+        .into();
+        let retriever = SchemaRetriever::default();
+        let resolved = resolve_associations(
+            &Config::default(),
+            associations,
+            &[],
+            base,
+            &retriever,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        // `$id` is ignored next to `$ref` before Draft 2019-09, so the root `$ref` is resolved against the schema location
+        let (valid, output) =
+            validate_resolved(resolved, base, &retriever, &None, None, &Limits::default());
+        assert!(!valid);
+        assert!(!output.contains("Failed"), "{output}");
+        assert_eq!(output.matches("INVALID").count(), 1, "{output}");
+        assert!(
+            output.contains("\"name\" is a required property"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(["scheval", "-ivscode", "schemas"]).unwrap();
+        assert_eq!(
+            (args.command, args.files.len()),
+            (Some(Command::Schemas), 0)
+        );
+        let args = parse_args(["scheval", "receipts.json", "./schemas"]).unwrap();
+        assert_eq!(args.command, None);
+        assert_eq!(
+            args.files,
+            [Path::new("receipts.json"), Path::new("./schemas")]
+        );
+        let error = parse_args(["scheval", "receipts.json", "schemas"]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_output_order() {
+        let validate_with = |jobs: usize| {
+            let pool = thread_pool(Some(jobs)).unwrap();
+            let associations = include::Vscode::with_base(TEST_DIR).get_associations();
+            pool.install(|| validate(associations))
+        };
+        let (valid, output) = validate_with(1);
+        assert!(valid && output.matches("VALID").count() > 1, "{output}");
+        for jobs in [2, 4, 8] {
+            assert_eq!(validate_with(jobs), (valid, output.clone()), "jobs: {jobs}");
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests_util {
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// Public constant for the path to the test data directory.
+    pub const TEST_DIR: &str = "tests/data";
+
+    /// Create a set of PathBuf from a list of paths.
+    pub fn btreeset_of_pathbuf(paths: &[&str]) -> std::collections::BTreeSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    /// Create a temporary directory, which is removed when dropped, even if the test panics.
+    pub fn temp_dir() -> TempDir {
+        tempfile::Builder::new()
+            .prefix("scheval-")
+            .tempdir()
+            .expect("Failed to create temporary directory")
+    }
+}
@@ -0,0 +1,183 @@
+//! Schema lockfile: Record a content hash for every schema used, so that schema changes are explicit.
+
+use crate::{
+    retrieve::SchemaRetriever,
+    vendor::{retrieve_refs, sha256_hex},
+    Schema,
+};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+use url::Url;
+
+/// Name of the lockfile in the base directory.
+pub(crate) const LOCKFILE: &str = "scheval.lock";
+
+/// Content hashes of schemas, keyed by their identity.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Lockfile {
+    /// Map from schema identity to the hex-encoded SHA-256 hash of its content.
+    schemas: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Load the lockfile in `base`, or `None` if there isn't one.
+    pub(crate) fn load(base: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = base.join(LOCKFILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let lock: Value = serde_json::from_slice(&fs::read(&path)?)?;
+        let Some(Value::Object(schemas)) = lock.get("schemas") else {
+            return Err(format!("Invalid lockfile `{}`", path.to_string_lossy()).into());
+        };
+        let mut lockfile = Self::default();
+        for (key, hash) in schemas {
+            let Value::String(hash) = hash else {
+                return Err(format!(
+                    "Invalid entry `{key}` in lockfile `{}`",
+                    path.to_string_lossy()
+                )
+                .into());
+            };
+            lockfile.schemas.insert(key.to_string(), hash.to_string());
+        }
+        Ok(Some(lockfile))
+    }
+
+    /// Write the lockfile into `base`.
+    pub(crate) fn write(&self, base: &Path) -> Result<(), Box<dyn Error>> {
+        let lock = json!({ "version": 1, "schemas": self.schemas });
+        fs::write(
+            base.join(LOCKFILE),
+            serde_json::to_string_pretty(&lock)? + "\n",
+        )?;
+        Ok(())
+    }
+
+    /// Record the resolved content `json` of the schema identified by `key` (see [`lock_key`]).
+    pub(crate) fn insert(&mut self, key: String, json: &Value) {
+        self.schemas.insert(key, content_hash(json));
+    }
+
+    /// Record the content of all schemas referenced by `json` at `base_uri` via `$ref`, transitively. Referenced local files are identified by their path relative to `base`.
+    pub(crate) fn insert_refs(
+        &mut self,
+        json: &Value,
+        base_uri: Option<&Url>,
+        base: &Path,
+        retriever: &SchemaRetriever,
+    ) -> Result<(), Box<dyn Error>> {
+        let base = base.canonicalize()?;
+        for (url, json) in retrieve_refs(json, base_uri, retriever)? {
+            self.insert(ref_key(&url, &base), &json);
+        }
+        Ok(())
+    }
+
+    /// Describe the differences from `locked`, the lockfile on disk. Empty if they match.
+    pub(crate) fn diff(&self, locked: &Self) -> Vec<String> {
+        let mut differences = Vec::new();
+        for (key, hash) in &self.schemas {
+            match locked.schemas.get(key) {
+                None => differences.push(format!("`{key}` is not locked")),
+                Some(locked_hash) if locked_hash != hash => {
+                    differences.push(format!("`{key}` has changed"))
+                }
+                _ => {}
+            }
+        }
+        for key in locked.schemas.keys() {
+            if !self.schemas.contains_key(key) {
+                differences.push(format!("`{key}` is locked but no longer used"));
+            }
+        }
+        differences
+    }
+
+    /// Get a hash of all recorded content hashes.
+    pub(crate) fn digest(&self) -> String {
+        sha256_hex(json!(self.schemas).to_string().as_bytes())
+    }
+
+    /// Number of locked schemas.
+    pub(crate) fn len(&self) -> usize {
+        self.schemas.len()
+    }
+}
+
+/// Get the identity of `schema` in the lockfile.
+pub(crate) fn lock_key(schema: &Schema) -> String {
+    match schema {
+        Schema::Local(path) => path.to_string_lossy().replace('\\', "/"),
+        Schema::Remote(url) => url.to_string(),
+        // Inline schemas have no identity other than their content
+        Schema::Inline(value) => format!("inline:{}", content_hash(value)),
+        Schema::Builtin(name) => format!("builtin:{name}"),
+    }
+}
+
+/// Get the identity of a referenced schema at `url` in the lockfile, relative to the canonicalized `base` if it's a local file under it.
+fn ref_key(url: &Url, base: &Path) -> String {
+    if url.scheme() == "file" {
+        if let Ok(path) = url.to_file_path() {
+            if let Ok(path) = path.strip_prefix(base) {
+                return path.to_string_lossy().replace('\\', "/");
+            }
+        }
+    }
+    url.to_string()
+}
+
+/// Get the hash of a resolved schema. Default `file://` `$id`s are ignored, so that the lockfile doesn't depend on where the project is checked out.
+fn content_hash(json: &Value) -> String {
+    let mut json = json.clone();
+    if let Value::Object(object) = &mut json {
+        for key in ["$id", "id"] {
+            if object
+                .get(key)
+                .and_then(Value::as_str)
+                .is_some_and(|id| id.starts_with("file:"))
+            {
+                object.remove(key);
+            }
+        }
+    }
+    sha256_hex(json.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_diff() {
+        let key = lock_key(&Schema::Local(PathBuf::from("schemas/a.schema.json")));
+        let builtin = lock_key(&Schema::Builtin("tsconfig".into()));
+        let mut locked = Lockfile::default();
+        locked.insert(
+            key.clone(),
+            &json!({ "$id": "file:///a/a.schema.json", "type": "string" }),
+        );
+        locked.insert(builtin.clone(), &json!({}));
+
+        // Checked out elsewhere, and the builtin schema is no longer used
+        let mut current = Lockfile::default();
+        current.insert(
+            key.clone(),
+            &json!({ "$id": "file:///b/a.schema.json", "type": "string" }),
+        );
+        assert_eq!(
+            current.diff(&locked),
+            ["`builtin:tsconfig` is locked but no longer used"]
+        );
+
+        let mut current = Lockfile::default();
+        current.insert(key, &json!({ "type": "number" }));
+        current.insert(builtin, &json!({}));
+        assert_eq!(
+            current.diff(&locked),
+            ["`schemas/a.schema.json` has changed"]
+        );
+    }
+}
@@ -1,18 +1,35 @@
 //! Main entry point for the scheval command line tool
 
-use scheval::{get_config, run};
+use scheval::{
+    clean_cache, get_bases, get_config, listed_files, lock, run_with_files, schemas, vendor, watch,
+    CacheCommand, Command,
+};
 
 /// Parse arguments and run scheval
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cfg = get_config();
-    match run(&cfg, ".") {
-        Ok(success) => {
-            if success {
-                Ok(())
-            } else {
-                Err("Validation failed".into())
-            }
-        }
-        Err(err) => Err(err),
+    let mut success = true;
+    // Read once, since the list might come from standard input
+    let listed = match cfg.command {
+        None => listed_files(&cfg)?,
+        Some(_) => None,
+    };
+    for base in get_bases(&cfg)? {
+        let result = match cfg.command {
+            Some(Command::Vendor) => vendor(&cfg, &base),
+            Some(Command::Lock) => lock(&cfg, &base),
+            Some(Command::Schemas) => schemas(&cfg, &base),
+            Some(Command::Watch) => watch(&cfg, &base),
+            Some(Command::Cache {
+                action: CacheCommand::Clean,
+            }) => clean_cache(&cfg, &base),
+            None => run_with_files(&cfg, &base, listed.as_ref()),
+        };
+        success &= result?;
+    }
+    if success {
+        Ok(())
+    } else {
+        Err("Validation failed".into())
     }
 }
@@ -0,0 +1,576 @@
+//! Meta-validation of schemas themselves: Check schemas against their meta-schemas, and look for unresolvable `$ref`s, unknown keywords and malformed regexes.
+
+use crate::{include::FileIndex, regularize, retrieve::SchemaRetriever, Schema};
+use jsonc_parser::{ast, parse_to_ast};
+use jsonschema::{Draft, Validator};
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::PathBuf,
+};
+use url::Url;
+
+/// Patterns of schema files under the base directory.
+const SCHEMA_FILE_PATTERNS: &[&str] = &["**/*.schema.json", "!**/node_modules"];
+
+/// Keywords defined by any of the supported drafts.
+const KEYWORDS: &[&str] = &[
+    // Core
+    "$schema",
+    "$id",
+    "id",
+    "$ref",
+    "$anchor",
+    "$dynamicRef",
+    "$dynamicAnchor",
+    "$recursiveRef",
+    "$recursiveAnchor",
+    "$vocabulary",
+    "$comment",
+    "$defs",
+    "definitions",
+    // Applicators
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "if",
+    "then",
+    "else",
+    "dependentSchemas",
+    "dependencies",
+    "prefixItems",
+    "items",
+    "additionalItems",
+    "contains",
+    "properties",
+    "patternProperties",
+    "additionalProperties",
+    "propertyNames",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+    // Validation
+    "type",
+    "enum",
+    "const",
+    "multipleOf",
+    "maximum",
+    "exclusiveMaximum",
+    "minimum",
+    "exclusiveMinimum",
+    "maxLength",
+    "minLength",
+    "pattern",
+    "maxItems",
+    "minItems",
+    "uniqueItems",
+    "maxContains",
+    "minContains",
+    "maxProperties",
+    "minProperties",
+    "required",
+    "dependentRequired",
+    // Format, content & meta-data
+    "format",
+    "contentEncoding",
+    "contentMediaType",
+    "contentSchema",
+    "title",
+    "description",
+    "default",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+    "examples",
+];
+
+/// Keywords recognized by VSCode, which are common in real-world schemas.
+// https://code.visualstudio.com/docs/languages/json#_json-schemas-and-settings
+const VSCODE_KEYWORDS: &[&str] = &[
+    "markdownDescription",
+    "deprecationMessage",
+    "markdownDeprecationMessage",
+    "errorMessage",
+    "patternErrorMessage",
+    "enumDescriptions",
+    "markdownEnumDescriptions",
+    "defaultSnippets",
+    "doNotSuggest",
+    "suggestSortText",
+    "allowComments",
+    "allowTrailingCommas",
+];
+
+/// Keywords whose values are maps from names to subschemas.
+const SCHEMA_MAP_KEYWORDS: &[&str] = &[
+    "properties",
+    "patternProperties",
+    "$defs",
+    "definitions",
+    "dependentSchemas",
+    "dependencies",
+];
+
+/// Keywords whose values are subschemas, or arrays of subschemas.
+const SCHEMA_KEYWORDS: &[&str] = &[
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "if",
+    "then",
+    "else",
+    "prefixItems",
+    "items",
+    "additionalItems",
+    "contains",
+    "additionalProperties",
+    "propertyNames",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+    "contentSchema",
+];
+
+/// Severity of a problem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a schema.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Problem {
+    pub(crate) severity: Severity,
+    /// JSON pointer to the offending value.
+    pub(crate) pointer: String,
+    pub(crate) message: String,
+}
+
+impl Problem {
+    pub(crate) fn error(pointer: &str, message: impl Display) -> Self {
+        Self {
+            severity: Severity::Error,
+            pointer: pointer.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn warning(pointer: &str, message: impl Display) -> Self {
+        Self {
+            severity: Severity::Warning,
+            pointer: pointer.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Checker of schemas, caching validators for meta-schemas and retrieved `$ref`s.
+pub(crate) struct MetaChecker<'a> {
+    retriever: &'a SchemaRetriever,
+    /// Validators for meta-schemas, keyed by draft or custom meta-schema URL.
+    meta_validators: HashMap<String, Option<Validator>>,
+    /// Retrieved external documents, or `None` if unresolvable.
+    documents: HashMap<String, Option<Value>>,
+}
+
+impl<'a> MetaChecker<'a> {
+    pub(crate) fn new(retriever: &'a SchemaRetriever) -> Self {
+        Self {
+            retriever,
+            meta_validators: HashMap::new(),
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Check `schema` at `base_uri`, using `draft` if it doesn't declare `$schema`.
+    pub(crate) fn check(
+        &mut self,
+        schema: &Value,
+        base_uri: Option<&Url>,
+        draft: Option<Draft>,
+    ) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        self.check_meta_schema(schema, draft, &mut problems);
+        let mut resources = HashMap::new();
+        collect_resources(schema, base_uri, &mut resources);
+        let mut walker = Walker {
+            checker: self,
+            resources: &resources,
+            problems: &mut problems,
+        };
+        walker.walk(schema, schema, base_uri, "");
+        problems
+    }
+
+    /// Validate `schema` against its meta-schema.
+    fn check_meta_schema(
+        &mut self,
+        schema: &Value,
+        draft: Option<Draft>,
+        problems: &mut Vec<Problem>,
+    ) {
+        let key = match schema.get("$schema") {
+            Some(Value::String(uri)) => match Draft::default().detect(schema) {
+                Ok(draft) => format!("{draft:?}"),
+                // Custom meta-schema
+                Err(_) => uri.trim_end_matches('#').to_string(),
+            },
+            Some(_) => {
+                problems.push(Problem::error("/$schema", "`$schema` is not a string"));
+                return;
+            }
+            None => format!("{:?}", draft.unwrap_or_default()),
+        };
+        if !self.meta_validators.contains_key(&key) {
+            let validator = self.meta_validator(&key);
+            self.meta_validators.insert(key.clone(), validator);
+        }
+        let Some(validator) = &self.meta_validators[&key] else {
+            problems.push(Problem::error(
+                "/$schema",
+                format!("Unresolvable meta-schema `{key}`"),
+            ));
+            return;
+        };
+        for error in validator.iter_errors(schema) {
+            let pointer = error.instance_path.to_string();
+            problems.push(Problem::error(&pointer, error));
+        }
+    }
+
+    /// Build a validator for the meta-schema identified by `key`.
+    fn meta_validator(&mut self, key: &str) -> Option<Validator> {
+        let (draft, meta_schema) = match key {
+            "Draft4" => (Draft::Draft4, referencing::meta::DRAFT4.clone()),
+            "Draft6" => (Draft::Draft6, referencing::meta::DRAFT6.clone()),
+            "Draft7" => (Draft::Draft7, referencing::meta::DRAFT7.clone()),
+            "Draft201909" => (Draft::Draft201909, referencing::meta::DRAFT201909.clone()),
+            "Draft202012" => (Draft::Draft202012, referencing::meta::DRAFT202012.clone()),
+            url => {
+                let meta_schema = self.retrieve(url)?;
+                (Draft::default().detect(&meta_schema).ok()?, meta_schema)
+            }
+        };
+        jsonschema::options()
+            .with_draft(draft)
+            .with_retriever(self.retriever.clone())
+            .build(&meta_schema)
+            .ok()
+    }
+
+    /// Retrieve the document at `url` (without fragment), or `None` if unresolvable.
+    fn retrieve(&mut self, url: &str) -> Option<Value> {
+        self.documents
+            .entry(url.to_string())
+            .or_insert_with(|| self.retriever.retrieve_url(url).ok())
+            .clone()
+    }
+}
+
+/// Walker over subschemas of a schema document.
+struct Walker<'a, 'b> {
+    checker: &'a mut MetaChecker<'b>,
+    /// Embedded resources of the document, keyed by their `$id`.
+    resources: &'a HashMap<String, &'a Value>,
+    problems: &'a mut Vec<Problem>,
+}
+
+impl Walker<'_, '_> {
+    /// Check `schema` at `pointer` and its subschemas, where `resource` is the closest enclosing resource.
+    fn walk(&mut self, schema: &Value, resource: &Value, base_uri: Option<&Url>, pointer: &str) {
+        let Value::Object(object) = schema else {
+            return;
+        };
+        let id = object
+            .get("$id")
+            .or_else(|| object.get("id"))
+            .and_then(Value::as_str)
+            .filter(|id| !id.starts_with('#'));
+        let joined;
+        let (resource, base_uri) = match id {
+            Some(id) => {
+                joined = match base_uri {
+                    Some(base_uri) => base_uri.join(id).ok(),
+                    None => Url::parse(id).ok(),
+                };
+                (schema, joined.as_ref())
+            }
+            None => (resource, base_uri),
+        };
+        for (key, value) in object {
+            let pointer = format!("{pointer}/{}", escape(key));
+            match key.as_str() {
+                "$ref" => {
+                    if let Value::String(reference) = value {
+                        self.check_ref(reference, resource, base_uri, &pointer);
+                    }
+                }
+                "pattern" => {
+                    if let Value::String(pattern) = value {
+                        self.check_regex(pattern, &pointer);
+                    }
+                }
+                _ => {}
+            }
+            if key == "patternProperties" {
+                if let Value::Object(properties) = value {
+                    for pattern in properties.keys() {
+                        self.check_regex(pattern, &format!("{pointer}/{}", escape(pattern)));
+                    }
+                }
+            }
+            if SCHEMA_MAP_KEYWORDS.contains(&key.as_str()) {
+                if let Value::Object(subschemas) = value {
+                    for (name, subschema) in subschemas {
+                        let pointer = format!("{pointer}/{}", escape(name));
+                        self.walk(subschema, resource, base_uri, &pointer);
+                    }
+                }
+            } else if SCHEMA_KEYWORDS.contains(&key.as_str()) {
+                match value {
+                    Value::Array(subschemas) => {
+                        for (i, subschema) in subschemas.iter().enumerate() {
+                            self.walk(subschema, resource, base_uri, &format!("{pointer}/{i}"));
+                        }
+                    }
+                    subschema => self.walk(subschema, resource, base_uri, &pointer),
+                }
+            } else if !KEYWORDS.contains(&key.as_str()) && !VSCODE_KEYWORDS.contains(&key.as_str())
+            {
+                self.problems.push(Problem::warning(
+                    &pointer,
+                    format!("Unknown keyword `{key}`"),
+                ));
+            }
+        }
+    }
+
+    /// Check that `reference` can be resolved.
+    fn check_ref(
+        &mut self,
+        reference: &str,
+        resource: &Value,
+        base_uri: Option<&Url>,
+        pointer: &str,
+    ) {
+        let (target, fragment) = reference.split_once('#').unwrap_or((reference, ""));
+        let document = if target.is_empty() {
+            resource.clone()
+        } else {
+            let url = match base_uri {
+                Some(base_uri) => base_uri.join(target),
+                None => Url::parse(target),
+            };
+            let Ok(url) = url else {
+                self.problems.push(Problem::error(
+                    pointer,
+                    format!("Invalid `$ref` `{reference}`"),
+                ));
+                return;
+            };
+            if let Some(embedded) = self.resources.get(url.as_str()) {
+                (*embedded).clone()
+            } else {
+                let Some(document) = self.checker.retrieve(url.as_str()) else {
+                    self.problems.push(Problem::error(
+                        pointer,
+                        format!("Unresolvable `$ref` `{reference}`"),
+                    ));
+                    return;
+                };
+                document
+            }
+        };
+        // Plain-name fragments refer to anchors, which aren't checked
+        if !fragment.is_empty() && !fragment.starts_with('/') {
+            return;
+        }
+        let fragment = percent_decode_str(fragment).decode_utf8_lossy();
+        if document.pointer(&fragment).is_none() {
+            self.problems.push(Problem::error(
+                pointer,
+                format!("Unresolvable `$ref` `{reference}`: No such pointer"),
+            ));
+        }
+    }
+
+    /// Check that `pattern` is a valid regular expression.
+    fn check_regex(&mut self, pattern: &str, pointer: &str) {
+        if let Err(error) = jsonschema::validator_for(&json!({ "pattern": pattern })) {
+            self.problems.push(Problem::error(
+                pointer,
+                format!("Malformed regex `{pattern}`: {error}"),
+            ));
+        }
+    }
+}
+
+/// Collect embedded resources (subschemas with `$id`) of `schema` into `resources`.
+fn collect_resources<'a>(
+    schema: &'a Value,
+    base_uri: Option<&Url>,
+    resources: &mut HashMap<String, &'a Value>,
+) {
+    match schema {
+        Value::Object(object) => {
+            let id = object
+                .get("$id")
+                .or_else(|| object.get("id"))
+                .and_then(Value::as_str);
+            let base_uri = match (base_uri, id) {
+                (Some(base_uri), Some(id)) => base_uri.join(id).ok(),
+                (None, Some(id)) => Url::parse(id).ok(),
+                (base_uri, None) => base_uri.cloned(),
+            };
+            if let (Some(base_uri), Some(_)) = (&base_uri, id) {
+                let mut uri = base_uri.clone();
+                uri.set_fragment(None);
+                resources.insert(uri.to_string(), schema);
+            }
+            for value in object.values() {
+                collect_resources(value, base_uri.as_ref(), resources);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                collect_resources(value, base_uri, resources);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Find schema files (`**/*.schema.json`) and JSON files under `schema_dirs` in `index`.
+pub(crate) fn find_schema_files(index: &FileIndex, schema_dirs: &[PathBuf]) -> HashSet<Schema> {
+    let mut schemas: HashSet<Schema> = index
+        .matching(SCHEMA_FILE_PATTERNS)
+        .into_iter()
+        .map(Schema::Local)
+        .collect();
+    for dir in schema_dirs {
+        let Ok(files) = index.json_files_under(dir) else {
+            eprintln!("Failed to list `{}`", dir.to_string_lossy());
+            continue;
+        };
+        schemas.extend(
+            files
+                .iter()
+                .map(|path| Schema::Local(regularize(index.base(), path))),
+        );
+    }
+    schemas
+}
+
+/// Escape a JSON pointer segment.
+fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+/// Locate the value at `pointer` in JSON (or JSONC) `text`, as 1-based line and column.
+pub(crate) fn locate(text: &str, pointer: &str) -> Option<(usize, usize)> {
+    let ast = parse_to_ast(text, &Default::default(), &Default::default())
+        .ok()?
+        .value?;
+    let mut node = &ast;
+    let mut offset = node_start(node);
+    let segments = pointer.split('/').skip(1);
+    for segment in segments {
+        let segment = segment.replace("~1", "/").replace("~0", "~");
+        match node {
+            ast::Value::Object(object) => {
+                let property = object
+                    .properties
+                    .iter()
+                    .find(|property| property.name.as_str() == segment)?;
+                offset = property.range.start;
+                node = &property.value;
+            }
+            ast::Value::Array(array) => {
+                node = array.elements.get(segment.parse::<usize>().ok()?)?;
+                offset = node_start(node);
+            }
+            _ => return None,
+        }
+    }
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    Some((line, column))
+}
+
+/// Get the start offset of an AST node.
+fn node_start(node: &ast::Value) -> usize {
+    use jsonc_parser::common::Ranged;
+    node.range().start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collect the set of problem pointers, for testing.
+    fn pointers(problems: &[Problem], severity: Severity) -> HashSet<&str> {
+        problems
+            .iter()
+            .filter(|problem| problem.severity == severity)
+            .map(|problem| problem.pointer.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let retriever = SchemaRetriever::default();
+        let mut checker = MetaChecker::new(&retriever);
+        let schema = json!({
+            "$id": "https://example.com/root.json",
+            "type": "object",
+            "properties": {
+                "count": { "type": "integer", "exclusiveMaximum": true },
+                "name": { "$ref": "#/$defs/missing" },
+                "nested": { "$ref": "nested.json#/$defs/ok" },
+                "code": { "pattern": "([a-z]" },
+                "custom": { "x-extra": 1, "markdownDescription": "Fine" }
+            },
+            "patternProperties": { "(": {} },
+            "$defs": {
+                "nested": { "$id": "nested.json", "$defs": { "ok": {} } }
+            }
+        });
+        let problems = checker.check(&schema, None, None);
+        assert_eq!(
+            pointers(&problems, Severity::Error),
+            HashSet::from([
+                "/properties/count/exclusiveMaximum",
+                "/properties/name/$ref",
+                "/properties/code/pattern",
+                "/patternProperties/(",
+            ])
+        );
+        assert_eq!(
+            pointers(&problems, Severity::Warning),
+            HashSet::from(["/properties/custom/x-extra"])
+        );
+
+        // Draft 4 allows boolean `exclusiveMaximum`
+        let legacy = json!({ "maximum": 10, "exclusiveMaximum": true });
+        assert!(checker.check(&legacy, None, Some(Draft::Draft4)).is_empty());
+    }
+
+    #[test]
+    fn test_locate() {
+        let text = "{\n    // Comment\n    \"a\": [1, {\"b/c\": 2}]\n}";
+        assert_eq!(locate(text, ""), Some((1, 1)));
+        assert_eq!(locate(text, "/a"), Some((3, 5)));
+        assert_eq!(locate(text, "/a/1/b~1c"), Some((3, 15)));
+        assert_eq!(locate(text, "/missing"), None);
+    }
+}
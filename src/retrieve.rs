@@ -0,0 +1,295 @@
+//! Retrieval of external resources referenced by `$ref` during schema compilation.
+
+use crate::{fetch_json, git::Staged, include::FileIndex, read_json, vendor::Vendored};
+use jsonschema::{Draft, Retrieve, Uri};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use url::Url;
+
+/// Retriever for `$ref`s, loading local files and fetching remote schemas. Cheap to clone.
+#[derive(Clone, Default)]
+pub(crate) struct SchemaRetriever {
+    /// Map from `$id` to paths of schemas in the configured schema directories.
+    index: Arc<HashMap<String, PathBuf>>,
+    /// URL prefix rewrites, sorted by descending length of the prefix.
+    rewrites: Arc<Vec<(String, String)>>,
+    /// Vendored copies of remote schemas.
+    vendored: Option<Arc<Vendored>>,
+    /// Documents retrieved so far, shared across compilations.
+    cache: Arc<Mutex<HashMap<String, Value>>>,
+    /// Snapshot of the git index to read local files from, if any.
+    staged: Option<Arc<Staged>>,
+}
+
+impl SchemaRetriever {
+    /// Resolve `$id`s of schemas under `schema_dirs` to local files, listed with `files` so that its excludes apply.
+    pub(crate) fn with_schema_dirs(mut self, schema_dirs: &[PathBuf], files: &FileIndex) -> Self {
+        let mut index = HashMap::new();
+        for dir in schema_dirs {
+            index_schema_dir(dir, files, &mut index);
+        }
+        self.index = Arc::new(index);
+        self
+    }
+
+    /// Read local files from a snapshot of the git index, if given.
+    pub(crate) fn with_staged(mut self, staged: Option<Arc<Staged>>) -> Self {
+        self.staged = staged;
+        self
+    }
+
+    /// Snapshot of the git index local files are read from, if any.
+    pub(crate) fn staged(&self) -> Option<&Staged> {
+        self.staged.as_deref()
+    }
+
+    /// Rewrite URLs starting with the first of each pair to start with the second, which is either a URL or a local path.
+    pub(crate) fn with_rewrites(mut self, rewrites: &[(String, String)]) -> Self {
+        let mut rewrites = rewrites.to_vec();
+        // Prefer the longest matching prefix
+        rewrites.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+        self.rewrites = Arc::new(rewrites);
+        self
+    }
+
+    /// Prefer vendored copies of remote schemas, if any.
+    pub(crate) fn with_vendored(mut self, vendored: Option<Vendored>) -> Self {
+        self.vendored = vendored.map(Arc::new);
+        self
+    }
+
+    /// Apply the first matching rewrite to `url`.
+    fn rewrite<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        for (from, to) in self.rewrites.iter() {
+            if let Some(rest) = url.strip_prefix(from.as_str()) {
+                return Cow::Owned(format!("{to}{rest}"));
+            }
+        }
+        Cow::Borrowed(url)
+    }
+
+    /// Retrieve the schema at `url`, preferring vendored copies, then applying rewrites and preferring local copies from schema directories.
+    pub(crate) fn retrieve_url(&self, url: &str) -> Result<Value, Box<dyn Error>> {
+        let url = normalize_id(url);
+        if let Some(json) = self.cache.lock().unwrap().get(url) {
+            return Ok(json.clone());
+        }
+        let json = self.retrieve_uncached(url)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), json.clone());
+        Ok(json)
+    }
+
+    /// Forget retrieved documents that are not fetched from remote, so that local files are read again.
+    pub(crate) fn forget_local(&self) {
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|url, _| self.local_path(url).is_none());
+    }
+
+    /// Get the local file that the schema at `url` is read from, if any.
+    pub(crate) fn local_path(&self, url: &str) -> Option<PathBuf> {
+        let url = self.rewrite(normalize_id(url));
+        let url = url.as_ref();
+        if let Some(path) = self.index.get(url) {
+            return Some(path.clone());
+        }
+        if url.starts_with("file:") {
+            return Url::parse(url).ok()?.to_file_path().ok();
+        }
+        (!url.contains(':')).then(|| PathBuf::from(url))
+    }
+
+    /// Retrieve the schema at normalized `url`, bypassing the cache.
+    fn retrieve_uncached(&self, url: &str) -> Result<Value, Box<dyn Error>> {
+        if let Some(json) = self
+            .vendored
+            .as_ref()
+            .and_then(|vendored| vendored.get(url))
+        {
+            return json;
+        }
+        let url = self.rewrite(url);
+        let url = url.as_ref();
+        if let Some(path) = self.index.get(url) {
+            return read_json(path, self.staged())?.map_err(Into::into);
+        }
+        if url.starts_with("file:") {
+            let Ok(path) = Url::parse(url)?.to_file_path() else {
+                return Err(format!("Invalid file URI `{url}`").into());
+            };
+            return read_json(&path, self.staged())?.map_err(Into::into);
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return fetch_json(url);
+        }
+        if !url.contains(':') {
+            // Rewritten to a local path
+            return read_json(Path::new(url), self.staged())?.map_err(Into::into);
+        }
+        Err(format!("Unsupported URI `{url}`").into())
+    }
+}
+
+impl Retrieve for SchemaRetriever {
+    fn retrieve(&self, uri: &Uri<&str>) -> Result<Value, Box<dyn Error + Send + Sync>> {
+        self.retrieve_url(uri.as_str())
+            .map_err(|error| error.to_string().into())
+    }
+}
+
+/// Strip the empty fragment (and trailing `#`) from a schema `$id`.
+fn normalize_id(id: &str) -> &str {
+    id.split_once('#').map_or(id, |(id, _)| id)
+}
+
+/// Check whether `schema` contains `$ref`s relative to its location, besides references within the same document.
+pub(crate) fn has_relative_refs(schema: &Value) -> bool {
+    match schema {
+        Value::Object(object) => {
+            let relative = matches!(
+                object.get("$ref"),
+                Some(Value::String(reference))
+                    if !reference.starts_with('#') && Url::parse(reference).is_err()
+            );
+            relative || object.values().any(has_relative_refs)
+        }
+        Value::Array(array) => array.iter().any(has_relative_refs),
+        _ => false,
+    }
+}
+
+/// Index all JSON files with an `$id` (or `id`) under `dir`, listed with `files`, into `index`.
+fn index_schema_dir(dir: &Path, files: &FileIndex, index: &mut HashMap<String, PathBuf>) {
+    let Ok(paths) = files.json_files_under(dir) else {
+        eprintln!(
+            "Failed to list schema directory `{}`",
+            dir.to_string_lossy()
+        );
+        return;
+    };
+    for path in paths {
+        let path = path.as_path();
+        let Ok(Ok(schema)) = read_json(path, files.staged().map(Arc::as_ref)) else {
+            eprintln!("Failed to read schema `{}`", path.to_string_lossy());
+            continue;
+        };
+        let Some(Value::String(id)) = schema.get("$id").or_else(|| schema.get("id")) else {
+            continue;
+        };
+        let id = normalize_id(id).to_string();
+        if let Some(existing) = index.get(&id) {
+            eprintln!(
+                "Duplicate `$id` `{id}` in `{}` and `{}`, using the former",
+                existing.to_string_lossy(),
+                path.to_string_lossy()
+            );
+            continue;
+        }
+        index.insert(id, path.to_path_buf());
+    }
+}
+
+/// Get the `file://` URI of given path. Note that given path must be absolute.
+pub(crate) fn file_uri(path: &Path) -> String {
+    Url::from_file_path(path)
+        .map(String::from)
+        .unwrap_or_else(|_| format!("file://{}", path.to_string_lossy()))
+}
+
+/// Set the `$id` (or `id` for Draft 4) of an inline schema to `uri` if absent, since it has no location of its own to resolve relative `$ref`s against.
+pub(crate) fn set_default_id(schema: &mut Value, uri: &str) {
+    let key = match Draft::default().detect(schema) {
+        Ok(Draft::Draft4) => "id",
+        _ => "$id",
+    };
+    if let Value::Object(object) = schema {
+        object
+            .entry(key)
+            .or_insert_with(|| Value::String(uri.to_string()));
+    }
+}
+
+/// Move the `$id` of a schema to `id` if absent, for schemas forced to Draft 4 which doesn't recognize `$id`.
+pub(crate) fn set_draft4_id(schema: &mut Value) {
+    if let Value::Object(object) = schema {
+        if !object.contains_key("id") {
+            if let Some(id) = object.remove("$id") {
+                object.insert("id".to_string(), id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::TEST_DIR;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema_dirs() {
+        let retriever = SchemaRetriever::default().with_schema_dirs(
+            &[Path::new(TEST_DIR).join("registry")],
+            &FileIndex::new(TEST_DIR),
+        );
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "address": { "$ref": "https://example.com/schemas/address.json" }
+            }
+        });
+        let validator = jsonschema::options()
+            .with_retriever(retriever)
+            .build(&schema)
+            .expect("Canonical URL should be resolved to local file");
+        assert!(validator.is_valid(&json!({ "address": { "city": "Paris" } })));
+        assert!(!validator.is_valid(&json!({ "address": { "city": 42 } })));
+    }
+
+    #[test]
+    fn test_rewrites() {
+        let mirror = format!("{TEST_DIR}/registry/");
+        let retriever = SchemaRetriever::default().with_rewrites(&[
+            (
+                "https://example.org/".to_string(),
+                "https://unreachable/".to_string(),
+            ),
+            ("https://example.org/mirrored/".to_string(), mirror),
+        ]);
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "address": { "$ref": "https://example.org/mirrored/address.json" }
+            }
+        });
+        let validator = jsonschema::options()
+            .with_retriever(retriever)
+            .build(&schema)
+            .expect("Rewritten URL should be resolved to local file");
+        assert!(validator.is_valid(&json!({ "address": { "city": "Paris" } })));
+        assert!(!validator.is_valid(&json!({ "address": { "city": 42 } })));
+    }
+
+    #[test]
+    fn test_has_relative_refs() {
+        assert!(!has_relative_refs(&json!({
+            "properties": {
+                "a": { "$ref": "#/$defs/a" },
+                "b": { "$ref": "https://example.com/b.json" }
+            }
+        })));
+        assert!(has_relative_refs(&json!({
+            "items": [{ "$ref": "./common.json#/$defs/name" }]
+        })));
+    }
+}
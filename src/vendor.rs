@@ -0,0 +1,429 @@
+//! Vendoring of remote schemas: Pin remote schemas and remote `$ref`s to local copies, recorded in a lockfile.
+
+use crate::{retrieve::SchemaRetriever, Schema};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+use url::Url;
+
+/// Default vendor directory, relative to the base directory.
+pub(crate) const DEFAULT_VENDOR_DIR: &str = ".scheval/vendor";
+/// Name of the lockfile in the vendor directory.
+const LOCKFILE: &str = "vendor.lock.json";
+
+/// Remote schemas vendored into a directory, as recorded in its lockfile.
+pub(crate) struct Vendored {
+    /// The vendor directory.
+    dir: PathBuf,
+    /// Map from URL to the path of the vendored copy (relative to `dir`) and its SHA-256 hash.
+    entries: HashMap<String, (PathBuf, String)>,
+}
+
+impl Vendored {
+    /// Load vendored schemas from the lockfile in `dir`, or `None` if there isn't one.
+    pub(crate) fn load(dir: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(schemas) = read_lock(dir)? else {
+            return Ok(None);
+        };
+        let lockfile = dir.join(LOCKFILE);
+        let mut entries = HashMap::new();
+        for (url, entry) in &schemas {
+            let (Some(Value::String(path)), Some(Value::String(hash))) =
+                (entry.get("path"), entry.get("sha256"))
+            else {
+                return Err(format!(
+                    "Invalid entry `{url}` in lockfile `{}`",
+                    lockfile.to_string_lossy()
+                )
+                .into());
+            };
+            let Some(path) = relative_path(path) else {
+                return Err(format!(
+                    "Path `{path}` of entry `{url}` in lockfile `{}` is outside the vendor directory",
+                    lockfile.to_string_lossy()
+                )
+                .into());
+            };
+            entries.insert(url.to_string(), (path, hash.to_string()));
+        }
+        Ok(Some(Self {
+            dir: dir.to_path_buf(),
+            entries,
+        }))
+    }
+
+    /// Get the vendored copy of the schema at `url`, verifying its hash. Returns `None` if it isn't vendored.
+    pub(crate) fn get(&self, url: &str) -> Option<Result<Value, Box<dyn Error>>> {
+        let (path, hash) = self.entries.get(url)?;
+        let path = self.dir.join(path);
+        let read = || -> Result<Value, Box<dyn Error>> {
+            let content = fs::read(&path)?;
+            if sha256_hex(&content) != *hash {
+                return Err(format!(
+                    "Vendored schema `{}` for `{url}` doesn't match the hash in the lockfile",
+                    path.to_string_lossy()
+                )
+                .into());
+            }
+            Ok(serde_json::from_slice(&content)?)
+        };
+        Some(read())
+    }
+}
+
+/// Read the `schemas` map from the lockfile in `dir`, or `None` if there isn't one.
+fn read_lock(dir: &Path) -> Result<Option<Map<String, Value>>, Box<dyn Error>> {
+    let lockfile = dir.join(LOCKFILE);
+    if !lockfile.exists() {
+        return Ok(None);
+    }
+    let mut lock: Value = serde_json::from_slice(&fs::read(&lockfile)?)?;
+    let Some(Value::Object(schemas)) = lock.get_mut("schemas").map(Value::take) else {
+        return Err(format!("Invalid lockfile `{}`", lockfile.to_string_lossy()).into());
+    };
+    Ok(Some(schemas))
+}
+
+/// Parse `path` of a lockfile entry, or `None` if it's absolute or contains `..`, thus might point outside the vendor directory.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then_some(path)
+}
+
+/// Get the hex-encoded SHA-256 hash of `content`.
+pub(crate) fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Get the path of the vendored copy of `url`, relative to the vendor directory.
+fn vendored_path(url: &Url) -> PathBuf {
+    let sanitize = |segment: &str| {
+        segment
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect::<String>()
+    };
+    let mut path = PathBuf::from(sanitize(url.host_str().unwrap_or("localhost")));
+    let segments = url.path_segments().into_iter().flatten();
+    for segment in segments.filter(|segment| !segment.is_empty() && *segment != "..") {
+        path.push(sanitize(segment));
+    }
+    if url.path().ends_with('/') {
+        path.push("index.json");
+    }
+    if let Some(query) = url.query() {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let file_name = format!("{file_name}_{}", sanitize(query));
+        path.set_file_name(file_name);
+    }
+    path
+}
+
+/// Collect URIs of `$ref`s in `schema` into `refs`, resolved against `base_uri` and nested `$id`s.
+pub(crate) fn collect_refs(schema: &Value, base_uri: Option<&Url>, refs: &mut Vec<Url>) {
+    match schema {
+        Value::Object(object) => {
+            let id = object
+                .get("$id")
+                .or_else(|| object.get("id"))
+                .and_then(Value::as_str);
+            let base_uri = match (base_uri, id) {
+                (Some(base_uri), Some(id)) => base_uri.join(id).ok(),
+                (None, Some(id)) => Url::parse(id).ok(),
+                (base_uri, None) => base_uri.cloned(),
+            };
+            if let Some(Value::String(reference)) = object.get("$ref") {
+                // References within the same document don't need retrieving
+                if !reference.starts_with('#') {
+                    let resolved = match &base_uri {
+                        Some(base_uri) => base_uri.join(reference),
+                        None => Url::parse(reference),
+                    };
+                    if let Ok(mut resolved) = resolved {
+                        resolved.set_fragment(None);
+                        refs.push(resolved);
+                    }
+                }
+            }
+            for value in object.values() {
+                collect_refs(value, base_uri.as_ref(), refs);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                collect_refs(value, base_uri, refs);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Retrieve all schemas referenced by `schema` at `base_uri` via `$ref` transitively, with their URLs.
+pub(crate) fn retrieve_refs(
+    schema: &Value,
+    base_uri: Option<&Url>,
+    retriever: &SchemaRetriever,
+) -> Result<Vec<(Url, Value)>, Box<dyn Error>> {
+    let mut pending = Vec::new();
+    collect_refs(schema, base_uri, &mut pending);
+    let mut visited = HashSet::new();
+    let mut retrieved = Vec::new();
+    while let Some(url) = pending.pop() {
+        if !visited.insert(url.clone()) {
+            continue;
+        }
+        let json = retriever.retrieve_url(url.as_str())?;
+        collect_refs(&json, Some(&url), &mut pending);
+        retrieved.push((url, json));
+    }
+    Ok(retrieved)
+}
+
+/// Get local files referenced by `schema` at `base_uri` via `$ref` transitively, without fetching remote schemas. Files that fail to be read are included, but their `$ref`s aren't followed.
+pub(crate) fn local_refs(
+    schema: &Value,
+    base_uri: Option<&Url>,
+    retriever: &SchemaRetriever,
+) -> Vec<PathBuf> {
+    let mut pending = Vec::new();
+    collect_refs(schema, base_uri, &mut pending);
+    let mut visited = HashSet::new();
+    let mut paths = Vec::new();
+    while let Some(url) = pending.pop() {
+        if !visited.insert(url.clone()) {
+            continue;
+        }
+        let Some(path) = retriever.local_path(url.as_str()) else {
+            continue;
+        };
+        paths.push(path);
+        // Unreadable files are reported on compilation
+        let Ok(json) = retriever.retrieve_url(url.as_str()) else {
+            continue;
+        };
+        collect_refs(&json, Some(&url), &mut pending);
+    }
+    paths
+}
+
+/// Download all remote schemas used by `schemas`, including remote `$ref`s transitively, into `dir` and write the lockfile. Returns the number of vendored schemas and whether all of them were retrieved successfully. Previously vendored copies are only removed if so.
+pub(crate) fn vendor_schemas(
+    schemas: Vec<Schema>,
+    base: &Path,
+    retriever: &SchemaRetriever,
+    dir: &Path,
+) -> Result<(usize, bool), Box<dyn Error>> {
+    let mut success = true;
+    let mut pending = Vec::new();
+    for schema in schemas {
+        match schema {
+            Schema::Remote(url) => match Url::parse(&url) {
+                Ok(url) => pending.push(url),
+                Err(error) => {
+                    eprintln!("Invalid schema URL `{url}`: {error}");
+                    success = false;
+                }
+            },
+            schema => {
+                let name = schema.to_string();
+                let base_uri = schema.base_uri(base);
+                match schema.resolve(base, retriever) {
+                    Ok(json) => collect_refs(&json, base_uri.as_ref(), &mut pending),
+                    Err(error) => {
+                        eprintln!("Failed to resolve schema `{name}`: {error}");
+                        success = false;
+                    }
+                }
+            }
+        }
+    }
+
+    let retrieved = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let mut visited = HashSet::new();
+    let mut lock = Map::new();
+    while let Some(url) = pending.pop() {
+        if !visited.insert(url.clone()) {
+            continue;
+        }
+        let json = match retriever.retrieve_url(url.as_str()) {
+            Ok(json) => json,
+            Err(error) => {
+                eprintln!("Failed to retrieve `{url}`: {error}");
+                success = false;
+                continue;
+            }
+        };
+        collect_refs(&json, Some(&url), &mut pending);
+        // Only remote schemas are vendored, while local ones are traversed for their `$ref`s
+        if !matches!(url.scheme(), "http" | "https") {
+            continue;
+        }
+        let path = vendored_path(&url);
+        let content = serde_json::to_string_pretty(&json)?;
+        let full_path = dir.join(&path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&full_path, &content)?;
+        println!("- `{url}` -> `{}`", full_path.to_string_lossy());
+        lock.insert(
+            url.to_string(),
+            json!({
+                "path": path.to_string_lossy().replace('\\', "/"),
+                "sha256": sha256_hex(content.as_bytes()),
+                "retrieved": retrieved,
+            }),
+        );
+    }
+
+    let count = lock.len();
+    for (url, entry) in read_lock(dir)?.unwrap_or_default() {
+        if lock.contains_key(&url) {
+            continue;
+        }
+        if success {
+            // Remove vendored copies that are no longer used
+            if let Some(path) = entry.get("path").and_then(Value::as_str) {
+                match relative_path(path) {
+                    Some(path) => {
+                        let _ = fs::remove_file(dir.join(path));
+                    }
+                    None => eprintln!(
+                        "Not removing `{path}` of entry `{url}`, which is outside the vendor directory"
+                    ),
+                }
+            }
+        } else {
+            // Keep previous copies, since they might have failed to be retrieved (e.g. when offline)
+            lock.insert(url, entry);
+        }
+    }
+
+    let lockfile = json!({ "version": 1, "schemas": lock });
+    fs::create_dir_all(dir)?;
+    fs::write(
+        dir.join(LOCKFILE),
+        serde_json::to_string_pretty(&lockfile)? + "\n",
+    )?;
+    Ok((count, success))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_refs() {
+        let schema = json!({
+            "$id": "https://example.com/schemas/root.json",
+            "properties": {
+                "local": { "$ref": "#/$defs/local" },
+                "relative": { "$ref": "./relative.json#/$defs/x" },
+                "absolute": { "$ref": "https://example.org/absolute.json" },
+                "nested": {
+                    "$id": "https://example.net/nested/",
+                    "items": { "$ref": "item.json" }
+                }
+            },
+            "$defs": { "local": { "type": "string" } }
+        });
+        let mut refs = Vec::new();
+        collect_refs(&schema, None, &mut refs);
+        let mut refs = refs.iter().map(Url::as_str).collect::<Vec<_>>();
+        refs.sort_unstable();
+        assert_eq!(
+            refs,
+            [
+                "https://example.com/schemas/relative.json",
+                "https://example.net/nested/item.json",
+                "https://example.org/absolute.json",
+            ]
+        );
+        assert_eq!(
+            vendored_path(&Url::parse("https://json.schemastore.org/package.json").unwrap()),
+            Path::new("json.schemastore.org/package.json")
+        );
+    }
+
+    #[test]
+    fn test_local_refs() {
+        let dir = Path::new(crate::tests_util::TEST_DIR)
+            .join("refs")
+            .canonicalize()
+            .unwrap();
+        let base_uri = Url::from_file_path(dir.join("schema.json")).unwrap();
+        let schema = json!({
+            "properties": {
+                "name": { "$ref": "./common.json#/$defs/name" },
+                "remote": { "$ref": "http://127.0.0.1:1/remote.json" }
+            }
+        });
+        // Local `$ref`s are collected even if remote ones fail to be retrieved
+        let paths = local_refs(&schema, Some(&base_uri), &SchemaRetriever::default());
+        assert_eq!(paths, [dir.join("common.json")]);
+    }
+
+    #[test]
+    fn test_vendor_failure() {
+        let temp = crate::tests_util::temp_dir();
+        let dir = temp.path();
+        let url = "http://127.0.0.1:1/old.json";
+        let path = vendored_path(&Url::parse(url).unwrap());
+        fs::create_dir_all(dir.join(&path).parent().unwrap()).unwrap();
+        fs::write(dir.join(&path), "{}").unwrap();
+        let lock = json!({
+            "version": 1,
+            "schemas": { url: { "path": path, "sha256": sha256_hex(b"{}"), "retrieved": "" } }
+        });
+        fs::write(dir.join(LOCKFILE), lock.to_string()).unwrap();
+        let retriever = SchemaRetriever::default();
+        // Previous copies are kept if retrieval fails, e.g. when offline
+        let schemas = vec![Schema::Remote(url.to_string())];
+        let (count, success) = vendor_schemas(schemas, dir, &retriever, dir).unwrap();
+        assert_eq!((count, success), (0, false));
+        let vendored = Vendored::load(dir).unwrap().unwrap();
+        assert_eq!(vendored.get(url).unwrap().unwrap(), json!({}));
+        // And removed once no longer used
+        let (count, success) = vendor_schemas(Vec::new(), dir, &retriever, dir).unwrap();
+        assert_eq!((count, success), (0, true));
+        assert!(Vendored::load(dir).unwrap().unwrap().get(url).is_none());
+        assert!(!dir.join(&path).exists());
+    }
+
+    #[test]
+    fn test_vendor_outside() {
+        let temp = crate::tests_util::temp_dir();
+        let dir = temp.path().join("vendor");
+        let outside = temp.path().join("outside.json");
+        fs::write(&outside, "{}").unwrap();
+        let url = "https://example.com/outside.json";
+        for path in [
+            outside.to_string_lossy().to_string(),
+            format!("../{}", outside.file_name().unwrap().to_string_lossy()),
+        ] {
+            let lock = json!({
+                "version": 1,
+                "schemas": { url: { "path": path, "sha256": sha256_hex(b"{}"), "retrieved": "" } }
+            });
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(LOCKFILE), lock.to_string()).unwrap();
+            // Paths outside the vendor directory are neither read nor removed
+            assert!(Vendored::load(&dir).is_err());
+            let retriever = SchemaRetriever::default();
+            let (count, success) = vendor_schemas(Vec::new(), &dir, &retriever, &dir).unwrap();
+            assert_eq!((count, success), (0, true));
+            assert!(outside.exists());
+        }
+    }
+}
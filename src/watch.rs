@@ -0,0 +1,385 @@
+//! Watch mode: Validate instances, then re-validate affected ones whenever files change.
+
+use crate::{
+    build_meta_validator, build_retriever,
+    cache::CACHE_DIR,
+    collect_associations, file_index, format_report,
+    include::{Drafts, FileIndex, CONFIG_FILES},
+    instance::{parse_instance, read_instance, Limits},
+    resolve_associations,
+    retrieve::SchemaRetriever,
+    thread_pool, vendor_dir, Config, Schema, FAILURE, SUCCESS,
+};
+use jsonschema::Validator;
+use notify::{
+    event::{EventKind, ModifyKind},
+    RecursiveMode, Watcher,
+};
+use rayon::{prelude::*, ThreadPool};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fmt::Write,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
+
+/// How long to wait for further events after a change, so that bursts (e.g. editors saving via a temporary file) are handled at once.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// A compiled schema, with results of validating its instances.
+struct WatchedSchema {
+    /// Identity of the schema (see `schema_identity`).
+    identity: String,
+    /// Names of the merged schemas.
+    names: Vec<String>,
+    /// The compiled schema, or the report of why it's invalid.
+    validator: Result<Arc<Validator>, String>,
+    /// Canonicalized paths to local files the schema is read from, including `$ref`s transitively.
+    dependencies: HashSet<PathBuf>,
+    /// Map from instance to whether it's valid and its report, or the error reading it.
+    results: BTreeMap<PathBuf, Result<(bool, String), String>>,
+}
+
+/// State of watch mode, updated on changes.
+struct Session<'a> {
+    config: &'a Config,
+    /// The base directory, as given.
+    base: &'a str,
+    /// Canonicalized path to the base directory.
+    canonical_base: PathBuf,
+    /// Index of the base directory, used to check for excluded paths.
+    index: Arc<FileIndex>,
+    retriever: SchemaRetriever,
+    meta_validator: Option<Validator>,
+    limits: Limits,
+    pool: ThreadPool,
+    associations: BTreeMap<Schema, BTreeSet<PathBuf>>,
+    /// Drafts of schemas given by including features.
+    drafts: Drafts,
+    schemas: Vec<WatchedSchema>,
+    /// Canonicalized paths to files outside of the association map whose changes require starting over: The catalog, the meta-schema, schema directories and the vendor directory.
+    environment: Vec<PathBuf>,
+}
+
+impl<'a> Session<'a> {
+    /// Start a session, validating all instances.
+    fn new(config: &'a Config, base: &'a str) -> Result<Self, Box<dyn Error>> {
+        let index = file_index(config, base)?;
+        let retriever = build_retriever(config, Path::new(base), &index)?;
+        let meta_validator = build_meta_validator(config, &retriever)?;
+        let (associations, drafts) = collect_associations(config, &index);
+        let environment = config
+            .catalog_path
+            .iter()
+            .chain(&config.meta_schema)
+            .chain(&config.schema_dirs)
+            .cloned()
+            .chain([vendor_dir(config, Path::new(base))])
+            .filter_map(|path| path.canonicalize().ok())
+            .collect();
+        let mut session = Self {
+            config,
+            base,
+            canonical_base: index.base().to_path_buf(),
+            associations,
+            drafts,
+            index,
+            retriever,
+            meta_validator,
+            // Instances are likely edited while watching
+            limits: Limits::new(config.max_file_size, config.max_depth).without_mmap(),
+            pool: thread_pool(config.jobs)?,
+            schemas: Vec::new(),
+            environment,
+        };
+        session.refresh(&HashSet::new())?;
+        Ok(session)
+    }
+
+    /// Rebuild associations, e.g. after files are created or removed.
+    fn rebuild_associations(&mut self) -> Result<(), Box<dyn Error>> {
+        self.index = file_index(self.config, self.base)?;
+        (self.associations, self.drafts) = collect_associations(self.config, &self.index);
+        Ok(())
+    }
+
+    /// Re-validate instances affected by `changed` files (canonicalized paths), returning the number of validated instances.
+    fn refresh(&mut self, changed: &HashSet<PathBuf>) -> Result<usize, Box<dyn Error>> {
+        let base = Path::new(self.base);
+        self.retriever.forget_local();
+        let resolved = resolve_associations(
+            self.config,
+            self.associations.clone(),
+            &self.drafts,
+            base,
+            &self.retriever,
+            |_, _, _| Ok(()),
+        )?;
+        let mut previous: HashMap<_, _> = self
+            .schemas
+            .drain(..)
+            .map(|schema| (schema.identity.clone(), schema))
+            .collect();
+        let mut schemas = Vec::new();
+        let mut jobs = Vec::new();
+        for resolved in resolved {
+            // Reuse the compiled schema and results if it's unchanged, including its `$ref`s
+            let previous = previous.remove(&resolved.identity).filter(|previous| {
+                previous.validator.is_ok() && previous.dependencies.is_disjoint(changed)
+            });
+            let (validator, dependencies, mut results) = match previous {
+                Some(previous) => (previous.validator, previous.dependencies, previous.results),
+                None => {
+                    let dependencies = resolved.dependencies(&self.retriever);
+                    let validator = resolved
+                        .compile(&self.retriever, &self.meta_validator)
+                        .map(Arc::new);
+                    (validator, dependencies, BTreeMap::new())
+                }
+            };
+            let mut kept = BTreeMap::new();
+            for instance in resolved.instances {
+                match results.remove(&instance) {
+                    Some(result) if !changed.contains(&self.canonical_base.join(&instance)) => {
+                        kept.insert(instance, result);
+                    }
+                    _ => {
+                        if let Ok(validator) = &validator {
+                            jobs.push((schemas.len(), Arc::clone(validator), instance));
+                        }
+                    }
+                }
+            }
+            schemas.push(WatchedSchema {
+                identity: resolved.identity,
+                names: resolved.names,
+                validator,
+                dependencies,
+                results: kept,
+            });
+        }
+        let validated = jobs.len();
+        let limits = &self.limits;
+        let results: Vec<_> = self.pool.install(|| {
+            jobs.into_par_iter()
+                .map(|(index, validator, instance)| {
+                    let result = validate(&validator, &base.join(&instance), limits)
+                        .map_err(|error| error.to_string());
+                    (index, instance, result)
+                })
+                .collect()
+        });
+        for (index, instance, result) in results {
+            schemas[index].results.insert(instance, result);
+        }
+        self.schemas = schemas;
+        Ok(validated)
+    }
+
+    /// Render a summary of problems, followed by counts.
+    fn summary(&self) -> Result<String, Box<dyn Error>> {
+        let mut summary = String::new();
+        let (mut instances, mut invalid, mut errors) = (0, 0, 0);
+        for schema in &self.schemas {
+            let mut problems = String::new();
+            match &schema.validator {
+                Err(error) => {
+                    writeln!(problems, "{error}")?;
+                    errors += 1;
+                }
+                Ok(_) => {
+                    for (instance, result) in &schema.results {
+                        instances += 1;
+                        match result {
+                            Ok((true, _)) => {}
+                            Ok((false, report)) => {
+                                problems.push_str(report);
+                                invalid += 1;
+                            }
+                            Err(error) => {
+                                let instance = Path::new(self.base).join(instance);
+                                writeln!(
+                                    problems,
+                                    "- `{}` - {FAILURE}ERROR{FAILURE:#}: {error}",
+                                    instance.to_string_lossy()
+                                )?;
+                                errors += 1;
+                            }
+                        }
+                    }
+                }
+            }
+            if !problems.is_empty() {
+                writeln!(summary, "Schema `{}`:", schema.names.join("`, `"))?;
+                writeln!(summary, "{problems}")?;
+            }
+        }
+        let style = if invalid + errors == 0 {
+            SUCCESS
+        } else {
+            FAILURE
+        };
+        writeln!(
+            summary,
+            "{style}{instances} instance(s) against {} schema(s): {invalid} invalid, {errors} error(s){style:#}",
+            self.schemas.len()
+        )?;
+        Ok(summary)
+    }
+
+    /// Check whether changes to `path` should be ignored.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.canonical_base) else {
+            return false;
+        };
+        relative.starts_with(".git")
+            || relative.starts_with(CACHE_DIR)
+            || self.index.is_excluded(relative)
+    }
+
+    /// Check whether changes to `path` require starting over.
+    fn is_environment(&self, path: &Path) -> bool {
+        self.environment.iter().any(|dir| path.starts_with(dir))
+    }
+}
+
+/// Validate the instance at `path` against `validator`, returning whether it's valid and the report to print.
+fn validate(
+    validator: &Validator,
+    path: &Path,
+    limits: &Limits,
+) -> Result<(bool, String), Box<dyn Error>> {
+    let instance_json = parse_instance(path, &read_instance(path, limits, None)?)?;
+    let errors: Vec<_> = validator
+        .iter_errors(&instance_json)
+        .map(|error| error.to_string())
+        .collect();
+    format_report(&path.to_string_lossy(), &errors)
+}
+
+/// Clear the terminal and print `text`, or just print it if stdout isn't a terminal.
+fn redraw(text: &str) {
+    if io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+    }
+    println!("{text}");
+}
+
+/// Validate instances, then re-validate affected ones whenever files under `base` change, until interrupted.
+pub(crate) fn watch(config: &Config, base: &str) -> Result<bool, Box<dyn Error>> {
+    if config.staged || config.since.is_some() {
+        return Err("Watch mode validates the working tree, and can't be used with `--staged`, `--changed` or `--since`".into());
+    }
+    if !config.files.is_empty() || config.files_from.is_some() {
+        return Err(
+            "Watch mode validates all discovered instances, and can't be used with given files"
+                .into(),
+        );
+    }
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut session = Session::new(config, base)?;
+    watcher.watch(&session.canonical_base, RecursiveMode::Recursive)?;
+    for path in &session.environment {
+        if !path.starts_with(&session.canonical_base) {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+        }
+    }
+    let watching = format!(
+        "Watching `{}` for changes, press Ctrl-C to stop",
+        session.canonical_base.to_string_lossy()
+    );
+    redraw(&format!("{}\n{watching}", session.summary()?));
+
+    while let Ok(event) = receiver.recv() {
+        let mut events = vec![event];
+        while let Ok(event) = receiver.recv_timeout(DEBOUNCE) {
+            events.push(event);
+        }
+        let started = Instant::now();
+        let mut changed = HashSet::new();
+        let (mut rebuild, mut restart) = (false, false);
+        for event in events {
+            let Ok(event) = event else {
+                eprintln!("Failed to watch: {}", event.unwrap_err());
+                continue;
+            };
+            let structural = match event.kind {
+                EventKind::Create(_) | EventKind::Remove(_) => true,
+                EventKind::Modify(ModifyKind::Name(_)) => true,
+                EventKind::Modify(_) => false,
+                _ => continue,
+            };
+            for path in event.paths {
+                // The vendor directory is excluded from the index, but changes to it still require starting over
+                let environment = session.is_environment(&path);
+                if !environment && session.is_ignored(&path) {
+                    continue;
+                }
+                rebuild |= structural || CONFIG_FILES.iter().any(|file| path.ends_with(file));
+                restart |= environment;
+                changed.insert(path);
+            }
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        let result = if restart {
+            Session::new(config, base).map(|new| {
+                session = new;
+                session.schemas.iter().map(|s| s.results.len()).sum()
+            })
+        } else if rebuild {
+            session
+                .rebuild_associations()
+                .and_then(|()| session.refresh(&changed))
+        } else {
+            session.refresh(&changed)
+        };
+        let status = match result {
+            Ok(validated) => format!(
+                "{}Validated {validated} instance(s) in {:.2?}\n{watching}",
+                session.summary()?,
+                started.elapsed()
+            ),
+            Err(error) => format!(
+                "{}{FAILURE}Error{FAILURE:#}: {error}\n{watching}",
+                session.summary()?
+            ),
+        };
+        redraw(&status);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests_util::TEST_DIR;
+
+    #[test]
+    fn test_refresh() {
+        let config = Config {
+            vscode: true,
+            ..Default::default()
+        };
+        let mut session = Session::new(&config, TEST_DIR).unwrap();
+        let base = session.canonical_base.clone();
+        assert_eq!(session.refresh(&HashSet::new()).unwrap(), 0);
+
+        // Only the changed instance is validated again
+        let changed = HashSet::from([base.join("receipts/1.json")]);
+        assert_eq!(session.refresh(&changed).unwrap(), 1);
+
+        // Instances of schemas referencing the changed file are validated again
+        let changed = HashSet::from([base.join("refs/common.json")]);
+        assert_eq!(session.refresh(&changed).unwrap(), 3);
+
+        let summary = session.summary().unwrap();
+        assert!(summary.contains(" 0 invalid, 0 error(s)"));
+    }
+}
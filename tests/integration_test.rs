@@ -1,7 +1,16 @@
-use scheval::{run, Config};
+use jsonschema::Draft;
+use scheval::{get_bases, run, Config, Schema};
 
 const TEST_DIR: &str = "tests/data";
 
+/// Create a temporary directory, which is removed when dropped, even if the test panics.
+fn temp_dir() -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix("scheval-")
+        .tempdir()
+        .expect("Failed to create temporary directory")
+}
+
 #[test]
 fn test_env() {
     // List files in test directory
@@ -24,8 +33,183 @@ fn test_env() {
 fn test_scheval() {
     let config = Config {
         vscode: true,
+        extension: true,
+        jetbrains: true,
+        manifest: true,
         suffix: true,
+        catalog: true,
+        builtin: cfg!(feature = "builtin-schemas"),
+        catalog_path: Some("tests/data/catalog/catalog.json".into()),
+        schema_dirs: vec!["tests/data/registry".into()],
+        // Legacy schema without `$schema`, relying on Draft 4 semantics
+        schema_drafts: vec![("legacy.schema.json".into(), Draft::Draft4)],
+        ..Default::default()
     };
     let result = run(&config, TEST_DIR).expect("Failed to run scheval");
     assert!(result);
 }
+
+#[test]
+fn test_schema_order() {
+    let mut schemas = [
+        Schema::Builtin("tsconfig".into()),
+        Schema::Inline(serde_json::json!({ "type": "object" })),
+        Schema::Remote("https://example.com/b.json".into()),
+        Schema::Local("b.schema.json".into()),
+        Schema::Remote("https://example.com/a.json".into()),
+        Schema::Local("a.schema.json".into()),
+    ];
+    schemas.sort();
+    let names: Vec<_> = schemas.iter().map(Schema::to_string).collect();
+    assert_eq!(
+        names,
+        [
+            "a.schema.json",
+            "b.schema.json",
+            "https://example.com/a.json",
+            "https://example.com/b.json",
+            "<inline schema>",
+            "<builtin schema: tsconfig>",
+        ]
+    );
+}
+
+#[test]
+fn test_files() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join(".vscode")).unwrap();
+    let settings =
+        r#"{ "json.schemas": [{ "fileMatch": ["/*.item.json"], "url": "./item.schema.json" }] }"#;
+    std::fs::write(dir.join(".vscode/settings.json"), settings).unwrap();
+    std::fs::write(dir.join("item.schema.json"), r#"{ "type": "object" }"#).unwrap();
+    std::fs::write(dir.join("valid.item.json"), "{}").unwrap();
+    std::fs::write(dir.join("invalid.item.json"), "[]").unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/package.json"), r#"{ "name": "sub" }"#).unwrap();
+    let run_with = |files: &[&str], files_from: Option<&str>| {
+        let config = Config {
+            vscode: true,
+            extension: true,
+            files: files.iter().map(|file| dir.join(file)).collect(),
+            files_from: files_from.map(|file| dir.join(file)),
+            ..Default::default()
+        };
+        run(&config, dir.to_str().unwrap()).expect("Failed to run scheval")
+    };
+    // Only given instances are validated
+    assert!(run_with(&["valid.item.json"], None));
+    assert!(!run_with(&["invalid.item.json"], None));
+    // Manifests not defining associations don't cause a full run
+    assert!(run_with(&["valid.item.json", "sub/package.json"], None));
+    // Instances of given schemas are validated
+    assert!(!run_with(&["item.schema.json"], None));
+    // Nothing to validate
+    assert!(run_with(&["missing.json"], None));
+    // NUL-separated list
+    let list = format!("{}\0", dir.join("valid.item.json").to_string_lossy());
+    std::fs::write(dir.join("list"), list).unwrap();
+    assert!(run_with(&[], Some("list")));
+}
+
+#[test]
+fn test_cache_parse_mode() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join(".vscode")).unwrap();
+    let settings = r#"{ "json.schemas": [{ "fileMatch": ["/tsconfig.json", "/base.json"], "url": "./config.schema.json" }] }"#;
+    std::fs::write(dir.join(".vscode/settings.json"), settings).unwrap();
+    std::fs::write(dir.join("config.schema.json"), r#"{ "type": "object" }"#).unwrap();
+    // Parsed as JSONC for `tsconfig.json`, but fails as strict JSON for `base.json`
+    let content = "{\n  // Comment\n}";
+    std::fs::write(dir.join("tsconfig.json"), content).unwrap();
+    std::fs::write(dir.join("base.json"), content).unwrap();
+    let config = Config {
+        vscode: true,
+        cache: true,
+        ..Default::default()
+    };
+    // A cached run fails just like the first one
+    for _ in 0..2 {
+        assert!(!run(&config, dir.to_str().unwrap()).expect("Failed to run scheval"));
+    }
+}
+
+#[test]
+fn test_vendor_excluded() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join(".vscode")).unwrap();
+    std::fs::create_dir_all(dir.join(".scheval/vendor/example.com")).unwrap();
+    let settings =
+        r#"{ "json.schemas": [{ "fileMatch": ["item.json"], "url": "./item.schema.json" }] }"#;
+    std::fs::write(dir.join(".vscode/settings.json"), settings).unwrap();
+    std::fs::write(dir.join("item.schema.json"), r#"{ "type": "object" }"#).unwrap();
+    std::fs::write(dir.join("item.json"), "{}").unwrap();
+    // Vendored copies keep the file names of their URLs, but aren't instances
+    std::fs::write(dir.join(".scheval/vendor/example.com/item.json"), "[]").unwrap();
+    let config = Config {
+        vscode: true,
+        ..Default::default()
+    };
+    assert!(run(&config, dir.to_str().unwrap()).expect("Failed to run scheval"));
+}
+
+#[test]
+fn test_bases() {
+    let bases = |config: Config| get_bases(&config).expect("Failed to get bases");
+    assert_eq!(bases(Config::default()), ["."]);
+    let config = Config {
+        bases: vec![".".into(), "tests/data/receipts/..".into()],
+        ..Default::default()
+    };
+    assert_eq!(bases(config), [".", "tests/data"]);
+    // The crate root has a `Cargo.toml`
+    let config = Config {
+        find_base: true,
+        ..Default::default()
+    };
+    assert_eq!(bases(config), ["."]);
+    let config = Config {
+        bases: vec!["tests/data/receipts.json".into()],
+        ..Default::default()
+    };
+    assert!(get_bases(&config).is_err());
+}
+
+#[test]
+fn test_files_from_stdin_with_bases() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let temp = temp_dir();
+    let dir = temp.path();
+    for base in ["a", "b"] {
+        std::fs::create_dir_all(dir.join(base).join(".vscode")).unwrap();
+        let settings = r#"{ "json.schemas": [{ "fileMatch": ["/*.item.json"], "url": "./item.schema.json" }] }"#;
+        std::fs::write(dir.join(base).join(".vscode/settings.json"), settings).unwrap();
+        std::fs::write(
+            dir.join(base).join("item.schema.json"),
+            r#"{ "type": "object" }"#,
+        )
+        .unwrap();
+    }
+    std::fs::write(dir.join("b/x.item.json"), "[]").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_scheval"))
+        .current_dir(dir)
+        .args(["-ivscode", "--no-cache", "--base", "a", "--base", "b"])
+        .args(["--files-from", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run scheval");
+    // The list is read once for all bases
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"b/x.item.json")
+        .unwrap();
+    assert!(!child.wait().unwrap().success());
+}